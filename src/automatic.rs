@@ -1,8 +1,20 @@
-use serenity::all::{Context, Message, Permissions};
+use std::sync::Arc;
+
+use serenity::all::{
+	Channel, ChannelId, Context, CreateAllowedMentions, CreateMessage, EditMessage, Message,
+	Permissions,
+};
 
 use crate::{
-	fix_existing_message::{can_suppress_embeds, fix_existing_message, try_react_and_suppress},
+	delete_button,
+	fix_existing_message::{
+		can_suppress_embeds, fix_existing_message, spawn_watch_for_missing_embeds,
+		try_react_and_suppress, unsuppress_embeds,
+	},
 	fix_link::LinkFixer,
+	replies::{Reply, ReplyStore},
+	resolver::Resolver,
+	settings::GuildSettings,
 };
 
 async fn get_permissions(context: &Context, message: &Message) -> Option<Permissions> {
@@ -12,26 +24,156 @@ async fn get_permissions(context: &Context, message: &Message) -> Option<Permiss
 	Some(guild.user_permissions_in(channel, member))
 }
 
-pub async fn fix_links(context: &Context, message: &Message, link_fix: &LinkFixer) {
+/// The channel, followed by what it is in: the channel of a thread or forum post, and the category of a channel.
+pub async fn channel_lineage(context: &Context, channel: ChannelId) -> Vec<ChannelId> {
+	let mut lineage = vec![channel];
+	// A thread is at most in a channel in a category.
+	for _ in 0..2 {
+		let current = lineage[lineage.len() - 1];
+		let Ok(Channel::Guild(channel)) = current.to_channel(context).await else {
+			break;
+		};
+		let Some(parent) = channel.parent_id else {
+			break;
+		};
+		lineage.push(parent);
+	}
+	lineage
+}
+
+pub async fn fix_links(
+	context: &Context,
+	message: &Message,
+	link_fix: &Arc<LinkFixer>,
+	resolver: &Arc<dyn Resolver>,
+	settings: &GuildSettings,
+	replies: &ReplyStore,
+) {
 	let permissions = get_permissions(context, message).await;
 
-	let Some((output, embeds_to_suppress)) = fix_existing_message(&message.content, link_fix).await
+	let Some(fixed) = fix_existing_message(&message.content, link_fix, &**resolver, settings).await
 	else {
 		return;
 	};
 
-	let Ok(own_message) = message.reply(&context.http, output).await else {
+	let reply = CreateMessage::new()
+		.content(&fixed.output)
+		.reference_message(message)
+		.allowed_mentions(CreateAllowedMentions::new().replied_user(false))
+		.components(vec![delete_button::create_button()]);
+	let Ok(own_message) = message.channel_id.send_message(&context.http, reply).await else {
 		println!("Did not remove embeds because message failed to send");
 		return;
 	};
+	replies
+		.record(
+			message.id,
+			Reply {
+				channel: message.channel_id,
+				reply: own_message.id,
+				author: message.author.id,
+			},
+			&link_fix.fixable_links(&message.content, settings),
+		)
+		.await;
+
+	try_react_and_suppress(
+		context,
+		message,
+		Some(&own_message),
+		fixed.embeds_to_suppress.clone(),
+		false,
+		settings.suppress_embeds && can_suppress_embeds(&permissions),
+		replies,
+	)
+	.await;
+
+	spawn_watch_for_missing_embeds(
+		context,
+		&message.content,
+		&own_message,
+		&fixed,
+		link_fix,
+		resolver,
+		settings,
+	);
+}
+
+/// Brings the reply in line with the edited original: edits it to the links fixed now, or deletes it if there are none left to fix.
+pub async fn update_reply(
+	context: &Context,
+	message: &Message,
+	reply: Reply,
+	link_fix: &Arc<LinkFixer>,
+	resolver: &Arc<dyn Resolver>,
+	settings: &GuildSettings,
+	replies: &ReplyStore,
+) {
+	let Some(fixed) = fix_existing_message(&message.content, link_fix, &**resolver, settings).await
+	else {
+		replies.take(message.id).await;
+		if let Err(error) = reply
+			.channel
+			.delete_message(&context.http, reply.reply)
+			.await
+		{
+			println!(
+				"Could not delete the reply to an edited message because {:?}",
+				error
+			);
+			return;
+		}
+		// The original may still link to something that embeds, just nothing to fix.
+		if let Some(suppression) = replies.take_suppression(reply.reply).await {
+			unsuppress_embeds(context, suppression.channel, suppression.original).await;
+		}
+		return;
+	};
+	let Ok(mut own_message) = reply.channel.message(context, reply.reply).await else {
+		// Someone else deleted it already.
+		replies.take(message.id).await;
+		return;
+	};
+	replies
+		.record(
+			message.id,
+			reply,
+			&link_fix.fixable_links(&message.content, settings),
+		)
+		.await;
+	if own_message.content == fixed.output {
+		return;
+	}
+	if let Err(error) = own_message
+		.edit(context, EditMessage::new().content(&fixed.output))
+		.await
+	{
+		println!(
+			"Could not edit the reply to an edited message because {:?}",
+			error
+		);
+		return;
+	}
 
+	let permissions = get_permissions(context, message).await;
 	try_react_and_suppress(
 		context,
 		message,
 		Some(&own_message),
-		embeds_to_suppress,
+		fixed.embeds_to_suppress.clone(),
 		false,
-		can_suppress_embeds(&permissions),
+		settings.suppress_embeds && can_suppress_embeds(&permissions),
+		replies,
 	)
 	.await;
+
+	spawn_watch_for_missing_embeds(
+		context,
+		&message.content,
+		&own_message,
+		&fixed,
+		link_fix,
+		resolver,
+		settings,
+	);
 }
@@ -0,0 +1,173 @@
+use regex::Regex;
+use regex_syntax::hir::{Class, Hir, HirKind, literal::Extractor};
+
+use crate::{
+	fix_link::{LinkFixer, RuleDefinition, parse_config},
+	link_fixer_handle::print_config_errors,
+	tokenizer::{SCHEMES, ends_link, find_links},
+};
+
+/// Checks the rules offline: runs every rule's examples, and looks for rules that can never match a link or are shadowed by an earlier rule. Shadowing is found through the examples, so rules without any are reported as not checked for it. Unlike loading the rules normally, this reports on every rule instead of stopping at failed examples. Prints a report and returns whether everything was fine.
+pub fn check_rules(config: &str) -> bool {
+	let definitions = match parse_config(config) {
+		Ok(definition) => definition.rules,
+		Err(error) => {
+			print_config_errors(&[error]);
+			return false;
+		}
+	};
+	let link_fixer = match LinkFixer::compile(config) {
+		Ok(link_fixer) => link_fixer,
+		Err(errors) => {
+			print_config_errors(&errors);
+			return false;
+		}
+	};
+
+	let mut problem_count = 0;
+	for (index, rule) in definitions.iter().enumerate() {
+		let problems = check_rule(index, rule, &definitions, &link_fixer);
+		if problems.is_empty() {
+			if rule.examples.is_empty() {
+				println!(
+					"{}: has no examples, so it was not checked for shadowing.",
+					rule.name
+				);
+			} else {
+				println!("{}: {} example(s) passed.", rule.name, rule.examples.len());
+			}
+		} else {
+			println!("{}: {} problem(s):", rule.name, problems.len());
+			for problem in &problems {
+				println!("- {problem}");
+			}
+			problem_count += problems.len();
+		}
+	}
+
+	if problem_count == 0 {
+		println!("All {} rules are fine.", definitions.len());
+	} else {
+		println!("Found {problem_count} problem(s).");
+	}
+	problem_count == 0
+}
+
+fn check_rule(
+	index: usize,
+	rule: &RuleDefinition,
+	definitions: &[RuleDefinition],
+	link_fixer: &LinkFixer,
+) -> Vec<String> {
+	let mut problems = Vec::new();
+	problems.extend(never_matches_links(&rule.pattern).map(String::from));
+
+	// The pattern was already checked by `LinkFixer::compile`.
+	let own_pattern = Regex::new(&format!("(?i)^(?:{})$", rule.pattern)).unwrap();
+	for example in &rule.examples {
+		let fixes = link_fixer
+			.find_and_fix_slash(&example.input)
+			.collect::<Vec<_>>();
+		for token in find_links(&example.input) {
+			if own_pattern.is_match(token.link)
+				&& let Some(fix) = fixes.iter().find(|fix| fix.span == token.span)
+				&& let Some(other_rule) = fix.rule
+				&& other_rule != index
+			{
+				problems.push(format!(
+					"\"{}\" is fixed by the earlier rule \"{}\" instead.",
+					token.link, definitions[other_rule].name
+				));
+			}
+		}
+	}
+	problems.extend(
+		link_fixer
+			.example_errors(index)
+			.into_iter()
+			.map(|error| error.to_string()),
+	);
+	problems
+}
+
+/// Why the pattern can never match a link the tokenizer finds, if it can't: it matches nothing at all, like with an empty character class, nothing that starts like a link, or only things a link would end before.
+fn never_matches_links(pattern: &str) -> Option<&'static str> {
+	// Letter case does not change any of this, and literals are easier to compare without case folding.
+	let hir = regex_syntax::Parser::new().parse(pattern).ok()?;
+	if hir.properties().minimum_len().is_none() {
+		return Some("The pattern can never match anything.");
+	}
+	if !can_start_like_link(&hir) {
+		return Some(
+			"The pattern can never match a link, since links start with http:// or https://.",
+		);
+	}
+	if always_contains(&hir, &ends_link) {
+		return Some(
+			"The pattern can never match a link, since it always needs whitespace or a `<`, which end links.",
+		);
+	}
+	None
+}
+
+/// Whether something the pattern matches could start with `http://` or `https://`, going by its possible prefixes. Patterns with too many prefixes to list are given the benefit of the doubt.
+fn can_start_like_link(hir: &Hir) -> bool {
+	let prefixes = Extractor::new().extract(hir);
+	let Some(prefixes) = prefixes.literals() else {
+		return true;
+	};
+	prefixes.iter().any(|prefix| {
+		let prefix = prefix.as_bytes().to_ascii_lowercase();
+		SCHEMES.iter().any(|scheme| {
+			prefix.starts_with(scheme.as_bytes()) || scheme.as_bytes().starts_with(&prefix)
+		})
+	})
+}
+
+/// Whether everything the pattern matches contains a character the test is true for.
+fn always_contains(hir: &Hir, test: &impl Fn(char) -> bool) -> bool {
+	match hir.kind() {
+		HirKind::Empty | HirKind::Look(_) => false,
+		HirKind::Literal(literal) => String::from_utf8_lossy(&literal.0).chars().any(test),
+		HirKind::Class(Class::Unicode(class)) => class
+			.ranges()
+			.iter()
+			.all(|range| (range.start()..=range.end()).all(test)),
+		HirKind::Class(Class::Bytes(class)) => class
+			.ranges()
+			.iter()
+			.all(|range| (range.start()..=range.end()).all(|byte| test(char::from(byte)))),
+		HirKind::Repetition(repetition) => {
+			repetition.min > 0 && always_contains(&repetition.sub, test)
+		}
+		HirKind::Capture(capture) => always_contains(&capture.sub, test),
+		HirKind::Concat(hirs) => hirs.iter().any(|hir| always_contains(hir, test)),
+		HirKind::Alternation(hirs) => hirs.iter().all(|hir| always_contains(hir, test)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn patterns_that_never_match_links() {
+		for pattern in [
+			r"https?://x\.com/\S+",
+			r"(?:https?://)?(?:www\.)?x\.com/\S*",
+			r".*\.example\.com/.*",
+			r"HTTPS://x\.com/[^ ]+ ?",
+		] {
+			assert_eq!(never_matches_links(pattern), None, "{pattern}");
+		}
+		for pattern in [
+			r"https://x\.com/[^\s\S]",
+			r"x\.com/\S+",
+			r"ftp://x\.com/.*",
+			r"https://x\.com/\S+ \S+",
+			r"https://x\.com/(?:a\s|<b)",
+		] {
+			assert!(never_matches_links(pattern).is_some(), "{pattern}");
+		}
+	}
+}
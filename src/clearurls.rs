@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fs};
+
+use percent_encoding::percent_decode_str;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::config_error::ConfigError;
+
+/// A ClearURLs rule database, as in their `data.min.json`.
+#[derive(Debug, Deserialize)]
+struct Database {
+	providers: HashMap<String, ProviderEntry>,
+}
+
+/// One provider in the database. `completeProvider` and `forceRedirection` are ignored, since the bot only rewrites links and does not block them.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderEntry {
+	url_pattern: String,
+	#[serde(default)]
+	rules: Vec<String>,
+	#[serde(default)]
+	raw_rules: Vec<String>,
+	#[serde(default)]
+	referral_marketing: Vec<String>,
+	#[serde(default)]
+	exceptions: Vec<String>,
+	#[serde(default)]
+	redirections: Vec<String>,
+}
+
+/// The rules for the links of one site, compiled.
+#[derive(Debug)]
+pub struct Provider {
+	url_pattern: Regex,
+	/// Matches the names of query parameters to remove.
+	parameters: Option<Regex>,
+	/// Matches parts of the link to remove outright.
+	raw_rules: Vec<Regex>,
+	/// Links the provider does not apply to, even if they match `url_pattern`.
+	exceptions: Vec<Regex>,
+	/// Links that redirect to another, with the target as the first capture group.
+	redirections: Vec<Regex>,
+}
+
+impl Provider {
+	fn from_entry(entry: ProviderEntry) -> Result<Self, regex::Error> {
+		let compile = |pattern: &String| RegexBuilder::new(pattern).case_insensitive(true).build();
+		let compile_all =
+			|patterns: &[String]| patterns.iter().map(compile).collect::<Result<Vec<_>, _>>();
+
+		let parameter_rules = entry
+			.rules
+			.iter()
+			.chain(&entry.referral_marketing)
+			.map(|rule| format!("(?:{rule})"))
+			.collect::<Vec<_>>();
+		let parameters = (!parameter_rules.is_empty())
+			.then(|| compile(&format!("^(?:{})$", parameter_rules.join("|"))))
+			.transpose()?;
+
+		Ok(Self {
+			url_pattern: compile(&entry.url_pattern)?,
+			parameters,
+			raw_rules: compile_all(&entry.raw_rules)?,
+			exceptions: compile_all(&entry.exceptions)?,
+			redirections: compile_all(&entry.redirections)?,
+		})
+	}
+	pub fn applies_to(&self, link: &str) -> bool {
+		self.url_pattern.is_match(link)
+			&& !self
+				.exceptions
+				.iter()
+				.any(|exception| exception.is_match(link))
+	}
+	/// The link this one redirects to, if it is a redirect link.
+	pub fn redirect(&self, link: &str) -> Option<String> {
+		self.redirections.iter().find_map(|redirection| {
+			let target = redirection.captures(link)?.get(1)?.as_str();
+			Some(percent_decode_str(target).decode_utf8_lossy().into_owned())
+		})
+	}
+	pub fn remove_raw_rules(&self, link: &str) -> String {
+		self.raw_rules.iter().fold(link.to_string(), |link, rule| {
+			rule.replace_all(&link, "").into_owned()
+		})
+	}
+	pub fn is_tracking_parameter(&self, name: &str) -> bool {
+		self.parameters
+			.as_ref()
+			.is_some_and(|parameters| parameters.is_match(name))
+	}
+}
+
+/// Reads a ClearURLs database. Providers using regex features the `regex` crate does not support, like lookaround, are skipped with a warning.
+pub fn parse_database(json: &str) -> Result<Vec<Provider>, serde_json::Error> {
+	let database: Database = serde_json::from_str(json)?;
+	let mut providers = Vec::with_capacity(database.providers.len());
+	for (name, entry) in database.providers {
+		match Provider::from_entry(entry) {
+			Ok(provider) => providers.push(provider),
+			Err(error) => eprintln!("Skipped ClearURLs provider \"{name}\": {error}"),
+		}
+	}
+	Ok(providers)
+}
+
+pub fn read_database(path: &str) -> Result<Vec<Provider>, ConfigError> {
+	let json = fs::read_to_string(path).map_err(|error| ConfigError::ClearUrls {
+		path: path.to_string(),
+		message: error.to_string(),
+	})?;
+	parse_database(&json).map_err(|error| ConfigError::ClearUrls {
+		path: path.to_string(),
+		message: error.to_string(),
+	})
+}
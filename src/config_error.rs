@@ -0,0 +1,164 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Where in the config a rule came from.
+#[derive(Debug, Clone)]
+pub struct RuleLocation {
+	/// The 0-based position of the rule in the config.
+	pub index: usize,
+	pub name: String,
+	/// The 1-based line the rule was found on.
+	pub line: usize,
+}
+
+impl Display for RuleLocation {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"rule {} (\"{}\", line {})",
+			self.index + 1,
+			self.name,
+			self.line
+		)
+	}
+}
+
+/// How an example input is given to the bot.
+#[derive(Debug, Clone, Copy)]
+pub enum ExampleMode {
+	/// Posted as a message.
+	Message,
+	/// Passed to `/fix`.
+	Slash,
+}
+
+impl Display for ExampleMode {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Message => write!(f, "as a message"),
+			Self::Slash => write!(f, "with /fix"),
+		}
+	}
+}
+
+/// A problem with the rule config.
+#[derive(Debug)]
+pub enum ConfigError {
+	/// The rule file is not valid TOML, or does not have the expected structure.
+	Malformed {
+		line: Option<usize>,
+		message: String,
+	},
+	/// A rule in the line-based format ended before its replacement or embed handling mode.
+	TruncatedRule {
+		index: usize,
+		line: usize,
+	},
+	/// A rule in the line-based format was not followed by an empty line.
+	ExpectedEmptyLine {
+		index: usize,
+		line: usize,
+		found: String,
+	},
+	InvalidPattern {
+		rule: RuleLocation,
+		error: regex::Error,
+	},
+	/// A placeholder in the replacement does not refer to any of the pattern's capture groups.
+	UnknownCaptureGroup {
+		rule: RuleLocation,
+		placeholder: String,
+	},
+	/// A placeholder in the replacement has a filter that does not exist.
+	UnknownFilter {
+		rule: RuleLocation,
+		filter: String,
+	},
+	UnknownEmbedHandling {
+		rule: RuleLocation,
+		mode: String,
+	},
+	/// One of the rule's examples did not give the expected output.
+	ExampleMismatch {
+		rule: RuleLocation,
+		mode: ExampleMode,
+		input: String,
+		expected: String,
+		output: String,
+	},
+	/// The rule has no replacement, but does not resolve links either.
+	MissingReplacement {
+		rule: RuleLocation,
+	},
+	/// The ClearURLs database could not be read.
+	ClearUrls {
+		path: String,
+		message: String,
+	},
+	/// The combined pattern of all the rules could not be built.
+	Megapattern(regex::Error),
+}
+
+impl Display for ConfigError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Malformed {
+				line: Some(line),
+				message,
+			} => write!(f, "Malformed rule file on line {line}: {message}"),
+			Self::Malformed {
+				line: None,
+				message,
+			} => write!(f, "Malformed rule file: {message}"),
+			Self::TruncatedRule { index, line } => write!(
+				f,
+				"Rule {} (line {}) is missing its replacement or embed handling mode.",
+				index + 1,
+				line
+			),
+			Self::ExpectedEmptyLine { index, line, found } => write!(
+				f,
+				"Expected line {} after rule {} to be empty, but found \"{}\".",
+				line,
+				index + 1,
+				found
+			),
+			Self::InvalidPattern { rule, error } => {
+				write!(f, "The pattern of {rule} is invalid: {error}")
+			}
+			Self::UnknownCaptureGroup { rule, placeholder } => write!(
+				f,
+				"The replacement of {rule} has a placeholder {{{placeholder}}}, but the pattern has no capture group by that number or name."
+			),
+			Self::UnknownFilter { rule, filter } => write!(
+				f,
+				"The replacement of {rule} uses a filter \"{filter}\", but the only filters are \"lower\", \"urlencode\", \"urldecode\", \"trim_slash\" and \"default:<value>\"."
+			),
+			Self::UnknownEmbedHandling { rule, mode } => write!(
+				f,
+				"The embed handling mode of {rule} is \"{mode}\", but the only options are \"replace\" and \"do nothing\"."
+			),
+			Self::ExampleMismatch {
+				rule,
+				mode,
+				input,
+				expected,
+				output,
+			} => write!(
+				f,
+				"An example of {rule} gave the wrong output: {mode}, \"{input}\" became \"{output}\" instead of \"{expected}\"."
+			),
+			Self::MissingReplacement { rule } => write!(
+				f,
+				"{rule} has no replacement, which only rules with `resolve = true` can do without."
+			),
+			Self::ClearUrls { path, message } => {
+				write!(f, "Could not read the ClearURLs database {path}: {message}")
+			}
+			Self::Megapattern(error) => {
+				write!(f, "Could not combine the rules into one pattern: {error}")
+			}
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
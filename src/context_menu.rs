@@ -5,7 +5,10 @@ use crate::{
 		can_react, can_suppress_embeds, fix_existing_message, try_react_and_suppress,
 	},
 	fix_link::LinkFixer,
+	replies::ReplyStore,
 	reply_shortcuts::ReplyShortcuts,
+	resolver::Resolver,
+	settings::GuildSettings,
 	strings::ERROR_NONE_FOUND,
 };
 
@@ -18,6 +21,9 @@ pub async fn fix_links(
 	context: &Context,
 	mut interaction: CommandInteraction,
 	link_fixer: &LinkFixer,
+	resolver: &dyn Resolver,
+	settings: &GuildSettings,
+	replies: &ReplyStore,
 ) {
 	let Some(message) = take_interacted_message(&mut interaction) else {
 		eprintln!("Did not find a message for some reason.");
@@ -27,8 +33,7 @@ pub async fn fix_links(
 		return;
 	};
 
-	let Some((output, embeds_to_suppress)) =
-		fix_existing_message(&message.content, link_fixer).await
+	let Some(fixed) = fix_existing_message(&message.content, link_fixer, resolver, settings).await
 	else {
 		let _ = interaction
 			.ephemeral_reply(&context.http, ERROR_NONE_FOUND)
@@ -36,18 +41,28 @@ pub async fn fix_links(
 		return;
 	};
 
-	let result = interaction.public_reply(&context.http, output).await;
+	let result = interaction.public_reply(&context.http, fixed.output).await;
 	if result.is_err() {
 		return;
 	};
+	// Editing the message later should not get it an automatic reply on top.
+	replies
+		.leave_alone(
+			message.id,
+			message.channel_id,
+			message.author.id,
+			&link_fixer.fixable_links(&message.content, settings),
+		)
+		.await;
 
 	try_react_and_suppress(
 		context,
 		&message,
 		interaction.get_response(&context.http).await.ok().as_ref(),
-		embeds_to_suppress,
-		can_react(&interaction.app_permissions),
-		can_suppress_embeds(&interaction.app_permissions),
+		fixed.embeds_to_suppress,
+		settings.reactions && can_react(&interaction.app_permissions),
+		settings.suppress_embeds && can_suppress_embeds(&interaction.app_permissions),
+		replies,
 	)
 	.await;
 }
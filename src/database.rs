@@ -0,0 +1,31 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::Connection;
+
+/// Where what the bot needs to remember is kept, like the settings of each server.
+pub const DATABASE_FILE: &str = "./settings.sqlite";
+
+/// A connection to the SQLite database, shared by everything stored in it. Queries block, so they run on a thread meant for that rather than on the async runtime.
+#[derive(Clone)]
+pub struct Database {
+	connection: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+	/// Opens the database at the path, creating it if needed. `:memory:` keeps it in memory only.
+	pub fn open(path: &str) -> rusqlite::Result<Self> {
+		Ok(Self {
+			connection: Arc::new(Mutex::new(Connection::open(path)?)),
+		})
+	}
+	/// Runs the queries without holding up the async runtime.
+	pub async fn run<T: Send + 'static>(
+		&self,
+		queries: impl FnOnce(&Connection) -> T + Send + 'static,
+	) -> T {
+		let connection = self.connection.clone();
+		tokio::task::spawn_blocking(move || queries(&connection.lock().unwrap()))
+			.await
+			.expect("A database query panicked")
+	}
+}
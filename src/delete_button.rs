@@ -0,0 +1,59 @@
+use serenity::all::*;
+
+use crate::{
+	fix_existing_message::unsuppress_embeds, replies::ReplyStore, reply_shortcuts::ReplyShortcuts,
+};
+
+/// The custom ID of the button on automatic replies that removes them.
+pub const DELETE_BUTTON_ID: &str = "delete_reply";
+
+pub fn create_button() -> CreateActionRow {
+	CreateActionRow::Buttons(vec![
+		CreateButton::new(DELETE_BUTTON_ID)
+			.style(ButtonStyle::Secondary)
+			.emoji(ReactionType::Unicode(String::from("🗑️"))),
+	])
+}
+
+/// Deletes the reply the button is on, if the original's author or a moderator pressed it, and gives the original back its embeds.
+pub async fn handle(context: &Context, interaction: ComponentInteraction, replies: &ReplyStore) {
+	let reply = &interaction.message;
+	let Some(original) = reply
+		.message_reference
+		.as_ref()
+		.and_then(|reference| reference.message_id)
+	else {
+		return;
+	};
+	// The original may be gone, leaving only what was remembered about it.
+	let author = match &reply.referenced_message {
+		Some(original) => Some(original.author.id),
+		None => replies.get(original).await.map(|reply| reply.author),
+	};
+	let is_moderator = interaction
+		.member
+		.as_ref()
+		.and_then(|member| member.permissions)
+		.is_some_and(|permissions| permissions.manage_messages());
+	if author != Some(interaction.user.id) && !is_moderator {
+		let _ = interaction
+			.ephemeral_reply(
+				&context.http,
+				"Only the author of the original message or a moderator can remove this.",
+			)
+			.await;
+		return;
+	}
+	let _ = interaction
+		.create_response(&context.http, CreateInteractionResponse::Acknowledge)
+		.await;
+	if let Err(error) = reply.delete(context).await {
+		println!("Could not delete a reply on request because {:?}", error);
+		return;
+	}
+	// Editing the original should not bring the reply back.
+	replies.dismiss(original).await;
+	if let Some(suppression) = replies.take_suppression(reply.id).await {
+		unsuppress_embeds(context, suppression.channel, suppression.original).await;
+	}
+}
@@ -1,49 +1,318 @@
+use std::{
+	collections::HashSet,
+	sync::{Arc, Mutex},
+};
+
 use itertools::Itertools;
 use serenity::{
-	all::{Command, Context, EventHandler, Interaction, Message, MessageUpdateEvent, Ready},
+	all::{
+		ChannelId, Command, Context, EventHandler, GuildId, Interaction, Message, MessageId,
+		MessageUpdateEvent, Ready, User,
+	},
 	async_trait,
 };
 
 use crate::{
-	automatic, context_menu,
+	automatic, context_menu, delete_button,
 	fix_existing_message::{
 		handle_bot_message_embed_generation, handle_user_message_embed_generation,
+		unsuppress_embeds,
 	},
-	fix_link::LinkFixer,
+	link_fixer_handle::LinkFixerHandle,
+	linkfix_command, owner_command,
+	replies::{self, ReplyStore},
+	resolver::Resolver,
+	settings::{GuildSettings, SettingsStore},
 	slash_command,
 };
 
 pub struct DiscordEventHandler {
-	link_fixer: LinkFixer,
+	link_fixer: Arc<LinkFixerHandle>,
+	/// For rules that follow redirects.
+	resolver: Arc<dyn Resolver>,
+	/// What each server changed about how the bot behaves there.
+	settings: Arc<SettingsStore>,
+	/// What the bot replied to on its own.
+	replies: Arc<ReplyStore>,
+	/// The messages being replied to right now, so events about them that come in meanwhile leave them alone.
+	replying: Mutex<HashSet<MessageId>>,
+}
+
+/// Keeps a message in `DiscordEventHandler::replying` until dropped.
+struct Replying<'h> {
+	replying: &'h Mutex<HashSet<MessageId>>,
+	message: MessageId,
+}
+
+impl Drop for Replying<'_> {
+	fn drop(&mut self) {
+		self.replying.lock().unwrap().remove(&self.message);
+	}
 }
 
 impl DiscordEventHandler {
-	pub fn new(link_fixer: LinkFixer) -> Self {
-		Self { link_fixer }
+	pub fn new(
+		link_fixer: Arc<LinkFixerHandle>,
+		resolver: Arc<dyn Resolver>,
+		settings: Arc<SettingsStore>,
+		replies: Arc<ReplyStore>,
+	) -> Self {
+		Self {
+			link_fixer,
+			resolver,
+			settings,
+			replies,
+			replying: Mutex::new(HashSet::new()),
+		}
+	}
+	/// Marks the message as being replied to, unless it already is.
+	fn start_replying(&self, message: MessageId) -> Option<Replying<'_>> {
+		self.replying
+			.lock()
+			.unwrap()
+			.insert(message)
+			.then_some(Replying {
+				replying: &self.replying,
+				message,
+			})
+	}
+	/// Whether automatic fixing is on where the message was posted and its author did not opt out.
+	async fn replies_automatically(
+		&self,
+		context: &Context,
+		settings: &GuildSettings,
+		guild: Option<GuildId>,
+		channel: ChannelId,
+		author: &User,
+	) -> bool {
+		let lineage = if settings.channels.is_empty() {
+			// Only the server's setting matters, so there's no need to look anything up.
+			vec![channel]
+		} else {
+			automatic::channel_lineage(context, channel).await
+		};
+		settings.automatic_in(&lineage) && !self.settings.is_opted_out(author.id, guild).await
+	}
+	/// Whether the bot replied to the message, or is about to, so the reply's embeds may take the place of its own.
+	async fn expects_reply(
+		&self,
+		context: &Context,
+		settings: &GuildSettings,
+		event: &MessageUpdateEvent,
+		author: &User,
+	) -> bool {
+		match self.replies.lookup(event.id).await {
+			// Fixed without a reply to take the place of its embeds, or the reply was dismissed.
+			Some(record) => record.reply.is_some(),
+			None => {
+				!author.bot
+					&& self
+						.replies_automatically(
+							context,
+							settings,
+							event.guild_id,
+							event.channel_id,
+							author,
+						)
+						.await
+			}
+		}
+	}
+	/// Replies to the message with its links fixed, if automatic fixing is on where it was posted and its author did not opt out.
+	async fn fix_automatically(&self, context: &Context, message: &Message) {
+		let settings = self.settings.get_optional(message.guild_id).await;
+		if self
+			.replies_automatically(
+				context,
+				&settings,
+				message.guild_id,
+				message.channel_id,
+				&message.author,
+			)
+			.await
+		{
+			automatic::fix_links(
+				context,
+				message,
+				&self.link_fixer.get().await,
+				&self.resolver,
+				&settings,
+				&self.replies,
+			)
+			.await;
+		}
+	}
+	/// Updates the bot's reply to match the edited message, or replies to it now if the edit added fixable links it did not have before. Leaves it alone if its fixable links did not change, or if they were fixed without a reply to keep up to date.
+	async fn handle_edit(
+		&self,
+		context: &Context,
+		event: &MessageUpdateEvent,
+		content: &str,
+		old: Option<&Message>,
+	) {
+		if !replies::is_remembered(event.id) {
+			// The reply to it may have been forgotten.
+			return;
+		}
+		let settings = self.settings.get_optional(event.guild_id).await;
+		let link_fixer = self.link_fixer.get().await;
+		let mut links = link_fixer.fixable_links(content, &settings);
+		links.sort_unstable();
+		let reply = match self.replies.lookup(event.id).await {
+			Some(record) => match record.reply {
+				Some(reply) if record.links != links => Some(reply),
+				_ => return,
+			},
+			// Without a record, the message may be from before the bot was around to reply to it, so only what the edit added is new.
+			None => {
+				let added_links = old.is_some_and(|old| {
+					let old_links = link_fixer.fixable_links(&old.content, &settings);
+					links.iter().any(|link| !old_links.contains(link))
+				});
+				if !added_links {
+					return;
+				}
+				None
+			}
+		};
+		let Some(_replying) = self.start_replying(event.id) else {
+			return;
+		};
+		let Ok(mut message) = context.http.get_message(event.channel_id, event.id).await else {
+			return;
+		};
+		// Messages fetched on their own don't say which server they are in.
+		message.guild_id = event.guild_id;
+		match reply {
+			Some(reply) => {
+				automatic::update_reply(
+					context,
+					&message,
+					reply,
+					&link_fixer,
+					&self.resolver,
+					&settings,
+					&self.replies,
+				)
+				.await
+			}
+			None => self.fix_automatically(context, &message).await,
+		}
+	}
+	/// Cleans up after a deleted message: deletes the bot's reply if it replied to it, and gives the original back its embeds if it was a reply that replaced them.
+	async fn forget_message(&self, context: &Context, message: MessageId) {
+		if let Some(suppression) = self.replies.take_suppression(message).await {
+			unsuppress_embeds(context, suppression.channel, suppression.original).await;
+		}
+		self.delete_reply(context, message).await;
+	}
+	/// Deletes the bot's reply to the message, if it made one.
+	async fn delete_reply(&self, context: &Context, original: MessageId) {
+		let Some(reply) = self.replies.take(original).await else {
+			return;
+		};
+		// There's nothing left to give the embeds back to.
+		self.replies.take_suppression(reply.reply).await;
+		if let Err(error) = reply
+			.channel
+			.delete_message(&context.http, reply.reply)
+			.await
+		{
+			println!(
+				"Could not delete the reply to a deleted message because {:?}",
+				error
+			);
+		}
 	}
 }
 
 #[async_trait]
 impl EventHandler for DiscordEventHandler {
 	async fn interaction_create(&self, context: Context, interaction: Interaction) {
-		let Interaction::Command(interaction) = interaction else {
-			return;
+		let interaction = match interaction {
+			Interaction::Command(interaction) => interaction,
+			Interaction::Component(interaction) => {
+				if interaction.data.custom_id == delete_button::DELETE_BUTTON_ID {
+					delete_button::handle(&context, interaction, &self.replies).await;
+				}
+				return;
+			}
+			Interaction::Autocomplete(interaction) => {
+				if interaction.data.name == "linkfix" {
+					linkfix_command::autocomplete(
+						&context,
+						interaction,
+						&*self.link_fixer.get().await,
+					)
+					.await;
+				}
+				return;
+			}
+			_ => return,
 		};
 		match interaction.data.name.as_str() {
-			"fix links" => context_menu::fix_links(&context, interaction, &self.link_fixer).await,
-			"fix" => slash_command::fix_links(&context, interaction, &self.link_fixer).await,
+			"fix links" => {
+				let settings = self.settings.get_optional(interaction.guild_id).await;
+				context_menu::fix_links(
+					&context,
+					interaction,
+					&*self.link_fixer.get().await,
+					&*self.resolver,
+					&settings,
+					&self.replies,
+				)
+				.await
+			}
+			"fix" => {
+				let settings = self.settings.get_optional(interaction.guild_id).await;
+				slash_command::fix_links(
+					&context,
+					interaction,
+					&*self.link_fixer.get().await,
+					&*self.resolver,
+					&settings,
+				)
+				.await
+			}
+			"linkfix" => {
+				linkfix_command::handle(&context, interaction, &self.link_fixer, &self.settings)
+					.await
+			}
+			"owner" => owner_command::handle(&context, interaction, &self.link_fixer).await,
 			_ => (),
 		}
 	}
 	async fn message(&self, context: Context, message: Message) {
-		if !message.author.bot {
-			automatic::fix_links(&context, &message, &self.link_fixer).await;
+		if !message.author.bot
+			&& let Some(_replying) = self.start_replying(message.id)
+		{
+			self.fix_automatically(&context, &message).await;
+		}
+	}
+	async fn message_delete(
+		&self,
+		context: Context,
+		_channel: ChannelId,
+		message: MessageId,
+		_guild: Option<GuildId>,
+	) {
+		self.forget_message(&context, message).await;
+	}
+	async fn message_delete_bulk(
+		&self,
+		context: Context,
+		_channel: ChannelId,
+		messages: Vec<MessageId>,
+		_guild: Option<GuildId>,
+	) {
+		for message in messages {
+			self.forget_message(&context, message).await;
 		}
 	}
 	async fn message_update(
 		&self,
 		context: Context,
-		_old: Option<Message>,
+		old: Option<Message>,
 		_new: Option<Message>,
 		event: MessageUpdateEvent,
 	) {
@@ -59,14 +328,31 @@ impl EventHandler for DiscordEventHandler {
 		// );
 		if *user == **context.cache.current_user() {
 			println!("Own message");
-			handle_bot_message_embed_generation(&context, &event).await;
-		} else if event
-			.embeds
-			.as_ref()
-			.is_some_and(|embeds| !embeds.is_empty())
+			handle_bot_message_embed_generation(&context, &event, &self.replies).await;
+			return;
+		}
+		// Updates that only add embeds to a message that was never edited don't come with an edit time. Those to a message that was do, and come with its content too, which is why edits that leave the fixable links alone are ignored.
+		if !user.bot
+			&& event.edited_timestamp.is_some()
+			&& let Some(content) = &event.content
 		{
+			self.handle_edit(&context, &event, content, old.as_ref())
+				.await;
+		}
+		if event.embeds.as_ref().is_none_or(|embeds| embeds.is_empty()) {
+			return;
+		}
+		let settings = self.settings.get_optional(event.guild_id).await;
+		if self.expects_reply(&context, &settings, &event, user).await {
 			println!("Other user's message with embeds.");
-			handle_user_message_embed_generation(&context, &event, &self.link_fixer).await;
+			handle_user_message_embed_generation(
+				&context,
+				&event,
+				&*self.link_fixer.get().await,
+				&settings,
+				&self.replies,
+			)
+			.await;
 		}
 	}
 	async fn ready(&self, context: Context, _ready: Ready) {
@@ -85,6 +371,8 @@ async fn maybe_register_commands(context: &Context) {
 		let commands = vec![
 			context_menu::create_command(),
 			slash_command::create_command(),
+			linkfix_command::create_command(),
+			owner_command::create_command(),
 		];
 		if Some("global") == arg2.as_deref() {
 			let resulting_commands = Command::set_global_commands(&context.http, commands.clone())
@@ -0,0 +1,46 @@
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+
+/// Everything but the characters that never need encoding in a URL.
+const URL_COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'_')
+	.remove(b'~');
+
+/// A transformation applied to a captured substring before it is inserted into the replacement, like `lower` in `{0|lower}`.
+#[derive(Debug)]
+pub enum Filter {
+	Lower,
+	/// Percent-encodes everything that might have a special meaning in a URL.
+	UrlEncode,
+	UrlDecode,
+	/// Removes slashes from the start and end.
+	TrimSlash,
+	/// Replaces an empty substring, including that of a group that did not match.
+	Default(String),
+}
+
+impl Filter {
+	pub fn from_string(string: &str) -> Option<Self> {
+		if let Some(value) = string.strip_prefix("default:") {
+			return Some(Self::Default(value.to_string()));
+		}
+		match string {
+			"lower" => Some(Self::Lower),
+			"urlencode" => Some(Self::UrlEncode),
+			"urldecode" => Some(Self::UrlDecode),
+			"trim_slash" => Some(Self::TrimSlash),
+			_ => None,
+		}
+	}
+	pub fn apply(&self, value: String) -> String {
+		match self {
+			Self::Lower => value.to_lowercase(),
+			Self::UrlEncode => utf8_percent_encode(&value, URL_COMPONENT).to_string(),
+			Self::UrlDecode => percent_decode_str(&value).decode_utf8_lossy().into_owned(),
+			Self::TrimSlash => value.trim_matches('/').to_string(),
+			Self::Default(default) if value.is_empty() => default.clone(),
+			Self::Default(_) => value,
+		}
+	}
+}
@@ -1,4 +1,8 @@
-use std::collections::{HashMap, hash_map};
+use std::{
+	collections::{HashMap, HashSet, hash_map},
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 use itertools::Itertools;
 use serenity::{
@@ -12,8 +16,13 @@ use serenity::{
 use tokio::sync::RwLock;
 
 use crate::{
-	fix_link::LinkFixer,
-	util::{count_embeds, get_embed_urls, has_spoilers, x_to_twitter},
+	fix_link::{LinkFixer, MirroredLink},
+	link_fixer_handle::LinkFixerHandle,
+	mirrors::{CheckSource, host_of},
+	replies::{ReplyStore, Suppression},
+	resolver::Resolver,
+	settings::GuildSettings,
+	util::{count_embeds, get_embed_urls, x_to_twitter},
 };
 
 /// A message with embeds that may be suppressed in the future, if their replacements succeed in generating.
@@ -23,13 +32,25 @@ struct BotMessage {
 	original_message: MessageId,
 	/// The number of embeds the bot message should have for it to be OK to suppress embeds on the original message. `None` if the bot message embeds have not yet been generated.
 	embed_count: Option<usize>,
+	added: Instant,
+}
+
+/// An original message with embeds, waiting for the bot message to get as many.
+#[derive(Debug)]
+struct OriginalMessage {
+	target_embed_count: usize,
+	added: Instant,
 }
 
 pub struct FutureEmbedRemovalsInner {
-	/// Key: original message, value: target embed count
-	messages_with_fixable_embeds: HashMap<MessageId, usize>,
+	/// Key: original message
+	messages_with_fixable_embeds: HashMap<MessageId, OriginalMessage>,
 	/// Key: bot message
 	bot_messages: HashMap<MessageId, BotMessage>,
+	/// How many original messages had their embeds replaced.
+	succeeded: u64,
+	/// How many messages were given up on, waiting for the other message of the pair.
+	timed_out: u64,
 }
 
 impl FutureEmbedRemovalsInner {
@@ -37,15 +58,44 @@ impl FutureEmbedRemovalsInner {
 		FutureEmbedRemovalsInner {
 			messages_with_fixable_embeds: HashMap::new(),
 			bot_messages: HashMap::new(),
+			succeeded: 0,
+			timed_out: 0,
 		}
 	}
+	/// When each waiting pairing started, by its original message, so a pairing with both of its messages waiting is one pairing.
+	fn pairings(&self) -> HashMap<MessageId, Instant> {
+		let mut pairings = self
+			.messages_with_fixable_embeds
+			.iter()
+			.map(|(&original_message, original)| (original_message, original.added))
+			.collect::<HashMap<_, _>>();
+		for bot_message in self.bot_messages.values() {
+			pairings
+				.entry(bot_message.original_message)
+				.and_modify(|added| *added = (*added).min(bot_message.added))
+				.or_insert(bot_message.added);
+		}
+		pairings
+	}
 }
 
 #[derive(Debug)]
 pub struct FutureEmbedRemovalsTypeMap;
 
 impl TypeMapKey for FutureEmbedRemovalsTypeMap {
-	type Value = FutureEmbedRemovals;
+	type Value = Arc<FutureEmbedRemovals>;
+}
+
+/// How often to give up on the pairings that took longer than the rules' pairing timeout.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How the pairing of messages with their bot messages has gone since the bot started.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PairingCounts {
+	pub succeeded: u64,
+	pub timed_out: u64,
+	/// Pairings still waiting for one of their messages.
+	pub waiting: usize,
 }
 
 pub struct FutureEmbedRemovals(RwLock<FutureEmbedRemovalsInner>);
@@ -64,9 +114,10 @@ impl FutureEmbedRemovals {
 		if let Some(embed_count) = embed_count
 			&& let hash_map::Entry::Occupied(occupied_entry) =
 				inner.messages_with_fixable_embeds.entry(original_message)
-			&& *occupied_entry.get() == embed_count
+			&& occupied_entry.get().target_embed_count == embed_count
 		{
 			occupied_entry.remove();
+			inner.succeeded += 1;
 			println!(
 				"Success! add_bot_message Removed embeds on {} due to {}",
 				original_message.get(),
@@ -79,6 +130,7 @@ impl FutureEmbedRemovals {
 			BotMessage {
 				original_message,
 				embed_count,
+				added: Instant::now(),
 			},
 		);
 		println!(
@@ -100,9 +152,10 @@ impl FutureEmbedRemovals {
 			);
 			return None;
 		};
-		if let Some(&target_embed_count) = inner
+		if let Some(target_embed_count) = inner
 			.messages_with_fixable_embeds
 			.get(&bot_message.original_message)
+			.map(|original_message| original_message.target_embed_count)
 		{
 			let original_message = bot_message.original_message;
 			// Both are found so bot message is no longer waiting, no matter which outcome.
@@ -110,6 +163,7 @@ impl FutureEmbedRemovals {
 			if target_embed_count == embed_count {
 				// Success! Remove original message too since it is no longer waiting on anything.
 				inner.messages_with_fixable_embeds.remove(&original_message);
+				inner.succeeded += 1;
 				println!(
 					"Success! update_bot_message Remove membeds on {} due to {}",
 					original_message.get(),
@@ -130,11 +184,12 @@ impl FutureEmbedRemovals {
 		);
 		None
 	}
+	/// Returns the bot message whose embeds the original's can now be replaced with, if there is one.
 	pub async fn add_original_message(
 		&self,
 		original_message: MessageId,
 		target_embed_count: usize,
-	) -> bool {
+	) -> Option<MessageId> {
 		let mut inner = self.0.write().await;
 		if let Some((&bot_message_id, bot_message)) = inner
 			.bot_messages
@@ -146,24 +201,78 @@ impl FutureEmbedRemovals {
 			inner.bot_messages.remove(&bot_message_id);
 			if embed_count == target_embed_count {
 				// Success.
+				inner.succeeded += 1;
 				println!(
 					"Success! add_original_message Removing embeds for {} due to {}",
 					original_message.get(),
 					bot_message_id.get()
 				);
-				return true;
+				return Some(bot_message_id);
 			}
 		}
 		// No match, so wait for the right bot message to come along.
-		inner
-			.messages_with_fixable_embeds
-			.insert(original_message, target_embed_count);
+		inner.messages_with_fixable_embeds.insert(
+			original_message,
+			OriginalMessage {
+				target_embed_count,
+				added: Instant::now(),
+			},
+		);
 		println!(
 			"Insert the target embed count {} for {}",
 			target_embed_count,
 			original_message.get()
 		);
-		false
+		None
+	}
+	/// Counts a pairing that succeeded without having to wait for either message.
+	pub async fn add_immediate_success(&self) {
+		self.0.write().await.succeeded += 1;
+	}
+	/// Gives up on the pairings that have been waiting for longer than the timeout, counting from their first message, and returns how many that was.
+	pub async fn sweep(&self, timeout: Duration) -> usize {
+		let mut inner = self.0.write().await;
+		let now = Instant::now();
+		let expired = inner
+			.pairings()
+			.into_iter()
+			.filter(|(_, added)| now.duration_since(*added) >= timeout)
+			.map(|(original_message, _)| original_message)
+			.collect::<HashSet<_>>();
+		inner
+			.messages_with_fixable_embeds
+			.retain(|original_message, _| !expired.contains(original_message));
+		inner
+			.bot_messages
+			.retain(|_, bot_message| !expired.contains(&bot_message.original_message));
+		inner.timed_out += expired.len() as u64;
+		expired.len()
+	}
+	pub async fn counts(&self) -> PairingCounts {
+		let inner = self.0.read().await;
+		PairingCounts {
+			succeeded: inner.succeeded,
+			timed_out: inner.timed_out,
+			waiting: inner.pairings().len(),
+		}
+	}
+}
+
+/// Every so often, gives up on the messages that have been waiting for longer than the rules' pairing timeout.
+pub async fn sweep_embed_removals(
+	handle: Arc<LinkFixerHandle>,
+	removals: Arc<FutureEmbedRemovals>,
+) {
+	loop {
+		tokio::time::sleep(SWEEP_INTERVAL).await;
+		let timeout = handle.get().await.pairing_timeout();
+		let timed_out = removals.sweep(timeout).await;
+		if timed_out > 0 {
+			println!(
+				"Gave up on {timed_out} message pairing(s) whose embeds did not match up within {} seconds.",
+				timeout.as_secs()
+			);
+		}
 	}
 }
 
@@ -179,21 +288,53 @@ pub fn can_suppress_embeds(permissions: &Option<Permissions>) -> bool {
 		.unwrap_or(false)
 }
 
-/// Take an existing message and fix any links it has. Returns `None` if there were none. Otherwise, returns the message with the fixed links and the list of links that were fixed that should end up with their embeds replaced.
+/// What the bot should reply to a message with.
+pub struct FixedMessage {
+	/// The fixed links.
+	pub output: String,
+	/// The links that were fixed that should end up with their embeds replaced.
+	pub embeds_to_suppress: Vec<String>,
+	/// The fixed links that should get embeds, which tell whether their mirrors work.
+	pub expected_embeds: Vec<ExpectedEmbed>,
+}
+
+/// A link on a mirror that the bot message should get an embed for.
+#[derive(Debug, Clone)]
+pub struct ExpectedEmbed {
+	pub mirror: MirroredLink,
+	/// The link that was fixed, like it is in `embeds_to_suppress`.
+	pub original: String,
+}
+
+impl ExpectedEmbed {
+	/// Whether the embed with this URL is for the link. Mirrors give their embeds either their own link or the original one.
+	fn is_for(&self, embed_url: &str) -> bool {
+		embed_url == self.mirror.link
+			|| embed_url == self.original
+			|| host_of(embed_url).is_some_and(|host| host.eq_ignore_ascii_case(&self.mirror.host))
+	}
+}
+
+/// Take an existing message and fix any links it has, with the settings of where it was posted. Returns `None` if there were none.
 pub async fn fix_existing_message(
 	content: &str,
 	link_fixer: &LinkFixer,
-) -> Option<(String, Vec<String>)> {
-	if has_spoilers(content) {
-		return None;
-	}
-
+	resolver: &dyn Resolver,
+	settings: &GuildSettings,
+) -> Option<FixedMessage> {
 	let mut fixed_urls = Vec::new();
+	let mut expected_embeds = Vec::new();
 	let output = link_fixer
-		.find_and_fix(content)
+		.find_and_fix_resolving(content, true, resolver, settings)
+		.await
+		.into_iter()
 		.map(|fix| {
 			if fix.remove_embed {
-				let url = x_to_twitter(fix.link).unwrap_or_else(|| fix.link.to_string());
+				let url = embed_url(fix.link);
+				expected_embeds.extend(fix.mirror.map(|mirror| ExpectedEmbed {
+					mirror,
+					original: url.clone(),
+				}));
 				fixed_urls.push(url);
 			}
 			fix.fixed
@@ -204,7 +345,105 @@ pub async fn fix_existing_message(
 		return None;
 	}
 
-	Some((output, fixed_urls))
+	Some(FixedMessage {
+		output,
+		embeds_to_suppress: fixed_urls,
+		expected_embeds,
+	})
+}
+
+/// The URL the embed of the link has, for comparing with the URLs of a message's embeds.
+fn embed_url(link: &str) -> String {
+	x_to_twitter(link).unwrap_or_else(|| link.to_string())
+}
+
+/// How long to give the bot message to get its embeds before deciding the mirrors it used are down.
+const EMBED_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Whether each mirror got an embed for at least one of its links, in the order the mirrors were first used.
+fn mirror_results<'e>(
+	expected_embeds: &'e [ExpectedEmbed],
+	embed_urls: &[String],
+) -> Vec<(&'e str, bool)> {
+	let mut results: Vec<(&str, bool)> = Vec::new();
+	for expected in expected_embeds {
+		let got_embed = embed_urls.iter().any(|url| expected.is_for(url));
+		match results
+			.iter_mut()
+			.find(|(host, _)| *host == expected.mirror.host)
+		{
+			Some((_, up)) => *up |= got_embed,
+			None => results.push((&expected.mirror.host, got_embed)),
+		}
+	}
+	results
+}
+
+/// Starts watching the bot message in the background, so the handler does not wait on it.
+pub fn spawn_watch_for_missing_embeds(
+	context: &Context,
+	original_content: &str,
+	bot_message: &Message,
+	fixed: &FixedMessage,
+	link_fixer: &Arc<LinkFixer>,
+	resolver: &Arc<dyn Resolver>,
+	settings: &GuildSettings,
+) {
+	if fixed.expected_embeds.is_empty() {
+		return;
+	}
+	tokio::spawn(watch_for_missing_embeds(
+		context.clone(),
+		original_content.to_string(),
+		bot_message.clone(),
+		fixed.expected_embeds.clone(),
+		link_fixer.clone(),
+		resolver.clone(),
+		settings.clone(),
+	));
+}
+
+/// Waits for the bot message to get its embeds, and records for each mirror it used whether that mirror's links got theirs. If some did not, edits it to use the next mirrors, if the rules have any and the failures added up to a mirror being down.
+async fn watch_for_missing_embeds(
+	context: Context,
+	original_content: String,
+	bot_message: Message,
+	expected_embeds: Vec<ExpectedEmbed>,
+	link_fixer: Arc<LinkFixer>,
+	resolver: Arc<dyn Resolver>,
+	settings: GuildSettings,
+) {
+	tokio::time::sleep(EMBED_TIMEOUT).await;
+	let Ok(current) = context
+		.http
+		.get_message(bot_message.channel_id, bot_message.id)
+		.await
+	else {
+		return;
+	};
+	let embed_urls = get_embed_urls(&current.embeds);
+	let results = mirror_results(&expected_embeds, &embed_urls);
+	for &(mirror, up) in &results {
+		link_fixer.mirrors().record(mirror, up, CheckSource::Embed);
+	}
+	if results.iter().all(|&(_, up)| up) {
+		return;
+	}
+	let Some(refixed) =
+		fix_existing_message(&original_content, &link_fixer, &*resolver, &settings).await
+	else {
+		return;
+	};
+	if refixed.output == current.content {
+		return;
+	}
+	if let Err(error) = EditMessage::new()
+		.content(refixed.output)
+		.execute(context, (current.channel_id, current.id, None))
+		.await
+	{
+		println!("Could not switch mirrors because {:?}", error);
+	}
 }
 
 /// Takes a list of URLs of existing embeds, and a list of links that should have embed-fixing versions posted by the bot, to determine how many embeds the bot message should end up with.
@@ -233,6 +472,7 @@ pub async fn try_react_and_suppress(
 	fixable_embed_links: Vec<String>,
 	can_react: bool,
 	can_suppress: bool,
+	replies: &ReplyStore,
 ) {
 	let react: OptionFuture<_> = can_react
 		.then(|| original_message.react(context, ReactionType::Unicode("🔧".to_string())))
@@ -246,6 +486,7 @@ pub async fn try_react_and_suppress(
 					original_message,
 					own_message,
 					fixable_embed_links,
+					replies,
 				)
 			})
 		})
@@ -260,7 +501,18 @@ async fn handle_embed_suppression(
 	original_message: &Message,
 	bot_message: &Message,
 	fixable_embed_links: Vec<String>,
+	replies: &ReplyStore,
 ) {
+	let suppression = |embed_count| Suppression {
+		channel: original_message.channel_id,
+		original: original_message.id,
+		embed_count,
+	};
+	let data = context.data.read().await;
+	let Some(removals) = data.get::<FutureEmbedRemovalsTypeMap>() else {
+		eprintln!("Couldn't get FutureEmbedRemovals.");
+		return;
+	};
 	if !original_message.embeds.is_empty() && !bot_message.embeds.is_empty() {
 		println!("Attempting to remove immediately as neither message's embed list is empty.");
 		// Both immediately have embeds, so try removing them now.
@@ -272,17 +524,14 @@ async fn handle_embed_suppression(
 		.unwrap_or(false)
 		{
 			println!("Success!");
-			suppress_embeds(context, original_message.channel_id, original_message.id).await;
+			removals.add_immediate_success().await;
+			let embed_count = count_embeds(&bot_message.embeds);
+			suppress_embeds(context, bot_message.id, suppression(embed_count), replies).await;
 		} else {
 			println!("Failure.");
 		}
 		return;
 	}
-	let data = context.data.read().await;
-	let Some(removals) = data.get::<FutureEmbedRemovalsTypeMap>() else {
-		eprintln!("Couldn't get FutureEmbedRemovals.");
-		return;
-	};
 	if !original_message.embeds.is_empty()
 		&& let Some(target_embed_count) = determine_target_embed_count(
 			get_embed_urls(&original_message.embeds),
@@ -298,11 +547,29 @@ async fn handle_embed_suppression(
 		.await
 	{
 		println!("Success upon adding bot message immediately.");
-		suppress_embeds(context, original_message.channel_id, original_message.id).await;
+		let embed_count = count_embeds(&bot_message.embeds);
+		suppress_embeds(context, bot_message.id, suppression(embed_count), replies).await;
 	}
 }
 
-pub async fn handle_bot_message_embed_generation(context: &Context, event: &MessageUpdateEvent) {
+/// Suppresses the embeds of the original message once the bot message has its own. If the bot message loses embeds after that, like when a mirror starts showing an error page instead, gives the original message back its embeds.
+pub async fn handle_bot_message_embed_generation(
+	context: &Context,
+	event: &MessageUpdateEvent,
+	replies: &ReplyStore,
+) {
+	if let Some(embeds) = &event.embeds
+		&& let Some(suppression) = replies.suppression(event.id).await
+		&& count_embeds(embeds) < suppression.embed_count
+	{
+		println!(
+			"Restoring embeds on {} as the fixed ones went away.",
+			suppression.original.get()
+		);
+		replies.take_suppression(event.id).await;
+		unsuppress_embeds(context, suppression.channel, suppression.original).await;
+		return;
+	}
 	let data = context.data.read().await;
 	let Some(removals) = data.get::<FutureEmbedRemovalsTypeMap>() else {
 		eprintln!("Future removals not present.");
@@ -315,7 +582,12 @@ pub async fn handle_bot_message_embed_generation(context: &Context, event: &Mess
 		.and_then(|embeds| (!embeds.is_empty()).then(|| count_embeds(embeds)))
 		&& let Some(message) = removals.update_bot_message(event.id, embed_count).await
 	{
-		suppress_embeds(context, event.channel_id, message).await;
+		let suppression = Suppression {
+			channel: event.channel_id,
+			original: message,
+			embed_count,
+		};
+		suppress_embeds(context, event.id, suppression, replies).await;
 	}
 }
 
@@ -323,7 +595,12 @@ pub async fn handle_user_message_embed_generation(
 	context: &Context,
 	event: &MessageUpdateEvent,
 	link_fixer: &LinkFixer,
+	settings: &GuildSettings,
+	replies: &ReplyStore,
 ) {
+	if !settings.suppress_embeds {
+		return;
+	}
 	let data = context.data.read().await;
 	let Some(removals) = data.get::<FutureEmbedRemovalsTypeMap>() else {
 		eprintln!("Future removals not present.");
@@ -339,30 +616,150 @@ pub async fn handle_user_message_embed_generation(
 	let Some(content) = event.content.as_ref() else {
 		return;
 	};
-	let Some((_output, embeds_to_suppress)) = fix_existing_message(content, link_fixer).await
-	else {
+	// This runs on every embed update, so it does without following redirects.
+	let embeds_to_suppress = link_fixer
+		.find_and_fix_message(content, settings)
+		.into_iter()
+		.filter(|fix| fix.remove_embed)
+		.map(|fix| embed_url(fix.link))
+		.collect::<Vec<_>>();
+	if embeds_to_suppress.is_empty() {
 		return;
-	};
+	}
 	let Some(target_embed_count) =
 		determine_target_embed_count(get_embed_urls(embeds), &embeds_to_suppress)
 	else {
 		return;
 	};
 
-	if removals
+	if let Some(bot_message) = removals
 		.add_original_message(event.id, target_embed_count)
 		.await
 	{
-		suppress_embeds(context, event.channel_id, event.id).await;
+		let suppression = Suppression {
+			channel: event.channel_id,
+			original: event.id,
+			embed_count: target_embed_count,
+		};
+		suppress_embeds(context, bot_message, suppression, replies).await;
 	}
 }
 
-async fn suppress_embeds(context: &Context, channel: ChannelId, message: MessageId) {
+/// Gives the message back the embeds that were suppressed.
+pub async fn unsuppress_embeds(context: &Context, channel: ChannelId, message: MessageId) {
 	if let Err(error) = EditMessage::new()
-		.suppress_embeds(true)
+		.suppress_embeds(false)
 		.execute(context, (channel, message, None))
 		.await
 	{
-		println!("Did not remove embeds because {:?}", error);
+		println!("Did not restore embeds because {:?}", error);
+	}
+}
+
+/// Suppresses the embeds of the original message, remembering that the bot message's embeds are why.
+async fn suppress_embeds(
+	context: &Context,
+	bot_message: MessageId,
+	suppression: Suppression,
+	replies: &ReplyStore,
+) {
+	match EditMessage::new()
+		.suppress_embeds(true)
+		.execute(context, (suppression.channel, suppression.original, None))
+		.await
+	{
+		Ok(_) => replies.record_suppression(bot_message, suppression).await,
+		Err(error) => println!("Did not remove embeds because {:?}", error),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::fix_link::DEFAULT_PAIRING_TIMEOUT;
+
+	#[test]
+	fn mirror_results_by_link() {
+		let expected = |host: &str, status: &str| ExpectedEmbed {
+			mirror: MirroredLink {
+				host: host.to_string(),
+				link: format!("https://{host}/a/status/{status}"),
+			},
+			original: format!("https://twitter.com/a/status/{status}"),
+		};
+		let expected_embeds = [
+			expected("fixupx.com", "1"),
+			expected("fixupx.com", "2"),
+			expected("vxtwitter.com", "3"),
+			expected("ddinstagram.com", "4"),
+		];
+		let embed_urls = [
+			String::from("https://twitter.com/a/status/2"),
+			String::from("https://vxtwitter.com/a/status/3"),
+			String::from("https://example.com/"),
+		];
+		assert_eq!(
+			mirror_results(&expected_embeds, &embed_urls),
+			[
+				("fixupx.com", true),
+				("vxtwitter.com", true),
+				("ddinstagram.com", false)
+			]
+		);
+	}
+	#[tokio::test]
+	async fn pairing_counts() {
+		let removals = FutureEmbedRemovals::new();
+		removals
+			.add_bot_message(MessageId::new(1), MessageId::new(2), Some(1))
+			.await;
+		assert_eq!(
+			removals.add_original_message(MessageId::new(1), 1).await,
+			Some(MessageId::new(2))
+		);
+		// Both messages of a pairing waiting, and a bot message and an original waiting on their own.
+		removals
+			.add_bot_message(MessageId::new(3), MessageId::new(4), None)
+			.await;
+		removals.add_original_message(MessageId::new(3), 2).await;
+		removals
+			.add_bot_message(MessageId::new(5), MessageId::new(6), None)
+			.await;
+		removals.add_original_message(MessageId::new(7), 2).await;
+		assert_eq!(removals.sweep(DEFAULT_PAIRING_TIMEOUT).await, 0);
+		assert_eq!(
+			removals.counts().await,
+			PairingCounts {
+				succeeded: 1,
+				timed_out: 0,
+				waiting: 3,
+			}
+		);
+		assert_eq!(removals.sweep(Duration::ZERO).await, 3);
+		assert_eq!(
+			removals.counts().await,
+			PairingCounts {
+				succeeded: 1,
+				timed_out: 3,
+				waiting: 0,
+			}
+		);
+		// An original waiting longer than its bot message still makes one pairing.
+		removals.add_original_message(MessageId::new(8), 1).await;
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		removals
+			.add_bot_message(MessageId::new(8), MessageId::new(9), None)
+			.await;
+		assert_eq!(removals.sweep(Duration::from_millis(25)).await, 1);
+		assert_eq!(removals.sweep(Duration::ZERO).await, 0);
+		removals.add_immediate_success().await;
+		assert_eq!(
+			removals.counts().await,
+			PairingCounts {
+				succeeded: 2,
+				timed_out: 4,
+				waiting: 0,
+			}
+		);
 	}
 }
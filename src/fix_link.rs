@@ -1,84 +1,369 @@
+use std::{collections::HashMap, ops::Range, sync::Arc, time::Duration};
+
 use itertools::Itertools;
 use regex::{Captures, Regex};
+use regex_syntax::ast::{self, Ast, GroupKind};
+use serenity::futures::future;
+
+use crate::{
+	clearurls,
+	config_error::{ConfigError, ExampleMode, RuleLocation},
+	filter::Filter,
+	markdown::excluded_ranges,
+	mirrors::{MirrorStatus, host_of},
+	resolver::{RESOLVE_TIMEOUT, Resolver},
+	rule_file::{RuleExample, is_rule_file, parse_rule_file},
+	settings::GuildSettings,
+	tokenizer::{LinkToken, find_links},
+	tracking::TrackingCleaner,
+};
 
 pub struct LinkFixer {
 	replacements: Vec<ReplacementRule>,
 	megapattern: Regex,
+	/// For links that no rule handles.
+	tracking: TrackingCleaner,
+	/// Whether to fix links in block quotes in messages.
+	fix_in_quotes: bool,
+	/// Which mirrors to avoid when a rule has several replacements.
+	mirrors: Arc<MirrorStatus>,
+	/// The mirrors to check the health of.
+	mirror_definitions: Vec<MirrorDefinition>,
+	/// How long to wait for a message and the bot's reply to both get their embeds, before giving up on replacing the message's.
+	pairing_timeout: Duration,
 }
 
 impl LinkFixer {
-	/// # Panics
+	/// Accepts either a structured rule file or the older line-based format.
 	///
-	/// Panics on malformed config.
-	pub fn from_config(config: &str) -> Self {
-		let replacements = process_replacement_rules(config);
-		let megapattern = make_megapattern(&replacements);
+	/// Returns every problem found with the config, rather than only the first, including examples that do not give the expected output.
+	pub fn from_config(config: &str) -> Result<Self, Vec<ConfigError>> {
+		let link_fixer = Self::compile(config)?;
+		let errors = (0..link_fixer.replacements.len())
+			.flat_map(|rule| link_fixer.example_errors(rule))
+			.collect::<Vec<_>>();
+		if !errors.is_empty() {
+			return Err(errors);
+		}
+		Ok(link_fixer)
+	}
+	/// Like `from_config`, but without running the examples.
+	pub fn compile(config: &str) -> Result<Self, Vec<ConfigError>> {
+		let definition = parse_config(config).map_err(|error| vec![error])?;
+		let insertion_point_regex = Regex::new(r"\{([^{}]+)}").unwrap();
+		let mut errors = Vec::new();
+		let replacements = definition
+			.rules
+			.iter()
+			.filter_map(|definition| {
+				ReplacementRule::from_config(definition, &insertion_point_regex)
+					.map_err(|rule_errors| errors.extend(rule_errors))
+					.ok()
+			})
+			.collect::<Vec<_>>();
+		if !errors.is_empty() {
+			return Err(errors);
+		}
+		let megapattern = make_megapattern(&replacements).map_err(|error| vec![error])?;
+		let providers = match &definition.clearurls {
+			Some(path) => clearurls::read_database(path).map_err(|error| vec![error])?,
+			None => Vec::new(),
+		};
 
+		// Each rule's pattern is in there wrapped in an extra group.
 		let group_sum = replacements
 			.iter()
-			.map(|r| r.capture_group_count)
-			.sum::<usize>()
-			* 2;
+			.map(|r| r.capture_group_count + 1)
+			.sum::<usize>();
 		let megapattern_group_count = megapattern.captures_len() - 1;
 		assert_eq!(
 			group_sum, megapattern_group_count,
 			"The megapattern has more groups than the replacements combined."
 		); // I am not sure whether this can actually fail, but it's definitely a problem if it does.
 
-		Self {
+		Ok(Self {
 			replacements,
 			megapattern,
+			tracking: TrackingCleaner::new(&definition.tracking_parameters, providers),
+			fix_in_quotes: definition.fix_in_quotes,
+			mirrors: Arc::new(MirrorStatus::new()),
+			mirror_definitions: definition.mirrors,
+			pairing_timeout: definition
+				.pairing_timeout
+				.unwrap_or(DEFAULT_PAIRING_TIMEOUT),
+		})
+	}
+	/// Makes the rules share what is known about which mirrors are down with earlier versions of them.
+	pub fn with_mirrors(mut self, mirrors: Arc<MirrorStatus>) -> Self {
+		self.mirrors = mirrors;
+		self
+	}
+	pub fn mirrors(&self) -> &MirrorStatus {
+		&self.mirrors
+	}
+	pub fn mirror_definitions(&self) -> &[MirrorDefinition] {
+		&self.mirror_definitions
+	}
+	pub fn pairing_timeout(&self) -> Duration {
+		self.pairing_timeout
+	}
+	/// The names of the loaded rules, in order.
+	pub fn rule_names(&self) -> impl Iterator<Item = &str> {
+		self.replacements
+			.iter()
+			.map(|rule| rule.location.name.as_str())
+	}
+	/// The hosts the rule's replacements link to, in order, or `None` if there is no such rule.
+	pub fn rule_mirrors(&self, name: &str) -> Option<Vec<&str>> {
+		let rule = self
+			.replacements
+			.iter()
+			.find(|rule| rule.location.name == name)?;
+		Some(rule.templates.iter().filter_map(Template::host).collect())
+	}
+	/// Runs the examples of one rule, returning the ones that did not give the expected output.
+	pub fn example_errors(&self, rule: usize) -> Vec<ConfigError> {
+		let rule = &self.replacements[rule];
+		let mut errors = Vec::new();
+		for example in &rule.examples {
+			if let Some(expected) = &example.message {
+				let output = self
+					.find_and_fix(&example.input)
+					.map(|fix| fix.fixed)
+					.join("\n");
+				if &output != expected {
+					errors.push(ConfigError::ExampleMismatch {
+						rule: rule.location.clone(),
+						mode: ExampleMode::Message,
+						input: example.input.clone(),
+						expected: expected.clone(),
+						output,
+					});
+				}
+			}
+			if let Some(expected) = &example.slash {
+				let output = self
+					.find_and_fix_slash(&example.input)
+					.map(|fix| fix.fixed)
+					.join("\n");
+				if &output != expected {
+					errors.push(ConfigError::ExampleMismatch {
+						rule: rule.location.clone(),
+						mode: ExampleMode::Slash,
+						input: example.input.clone(),
+						expected: expected.clone(),
+						output,
+					});
+				}
+			}
 		}
+		errors
 	}
+	/// Fixes the links in a message with the default settings, without following any redirects.
 	pub fn find_and_fix<'s>(&'s self, text: &'s str) -> impl Iterator<Item = LinkFix<'s>> + 's {
-		text.split_ascii_whitespace()
-			.flat_map(|text| self.megapattern.captures_iter(text))
-			.filter_map(|captures| LinkFix::new(captures, &self.replacements, true))
+		self.fix_all(text, true, None, &GuildSettings::default())
+			.into_iter()
+	}
+	/// Fixes the links in a message with the settings, without following any redirects, for when that is good enough and needs to be cheap.
+	pub fn find_and_fix_message<'s>(
+		&'s self,
+		text: &'s str,
+		settings: &GuildSettings,
+	) -> Vec<LinkFix<'s>> {
+		self.fix_all(text, true, None, settings)
 	}
+	/// Fixes the links given to the slash command with the default settings, without following any redirects.
 	pub fn find_and_fix_slash<'s>(
 		&'s self,
 		text: &'s str,
 	) -> impl Iterator<Item = LinkFix<'s>> + 's {
-		text.split_ascii_whitespace()
-			.flat_map(|text| self.megapattern.captures_iter(text))
-			.filter_map(|captures| LinkFix::new(captures, &self.replacements, false))
+		self.fix_all(text, false, None, &GuildSettings::default())
+			.into_iter()
+	}
+	/// Fixes the links, first following the redirects of the ones whose rules ask for it, and then fixing where they lead instead. Links that can't be resolved within `RESOLVE_TIMEOUT` get their rule's own replacement, if it has one. Rules the settings disable are skipped.
+	pub async fn find_and_fix_resolving<'s>(
+		&'s self,
+		text: &'s str,
+		was_message: bool,
+		resolver: &dyn Resolver,
+		settings: &GuildSettings,
+	) -> Vec<LinkFix<'s>> {
+		let links = self
+			.tokens(text, was_message)
+			.into_iter()
+			.map(|token| token.link)
+			.filter(|link| self.resolves(link, settings))
+			.unique()
+			.collect::<Vec<_>>();
+		let resolved = future::join_all(
+			links
+				.iter()
+				.map(|link| tokio::time::timeout(RESOLVE_TIMEOUT, resolver.resolve(link))),
+		)
+		.await;
+		let resolutions = links
+			.into_iter()
+			.zip(resolved)
+			.filter_map(|(link, resolved)| Some((link.to_string(), resolved.ok()??)))
+			.collect::<Resolutions>();
+		self.fix_all(text, was_message, Some(&resolutions), settings)
+	}
+	/// The links in a message that could get fixed, without following any redirects, so it is cheap to tell whether an edit changed anything that matters.
+	pub fn fixable_links<'s>(&self, text: &'s str, settings: &GuildSettings) -> Vec<&'s str> {
+		self.tokens(text, true)
+			.into_iter()
+			.filter(|token| {
+				self.resolves(token.link, settings)
+					|| self.fix_link(token.clone(), true, None, settings).is_some()
+			})
+			.map(|token| token.link)
+			.unique()
+			.collect()
+	}
+	/// Whether the link would be followed to where it leads before fixing it.
+	fn resolves(&self, link: &str, settings: &GuildSettings) -> bool {
+		self.megapattern.captures(link).is_some_and(|captures| {
+			let (_, rule, _) = matched_rule(&captures, &self.replacements);
+			rule.resolve && settings.rule_enabled(&rule.location.name)
+		})
+	}
+	fn fix_all<'s>(
+		&self,
+		text: &'s str,
+		was_message: bool,
+		resolutions: Option<&Resolutions>,
+		settings: &GuildSettings,
+	) -> Vec<LinkFix<'s>> {
+		self.tokens(text, was_message)
+			.into_iter()
+			.filter_map(|token| self.fix_link(token, was_message, resolutions, settings))
+			.collect()
+	}
+
+	/// The links to fix. In messages, the ones in code and, depending on the config, in block quotes are skipped, since they are presumably not meant to be clicked. The slash command fixes everything it is explicitly given.
+	fn tokens<'s>(&self, text: &'s str, was_message: bool) -> Vec<LinkToken<'s>> {
+		let mut tokens = find_links(text);
+		if was_message {
+			let excluded = excluded_ranges(text, self.fix_in_quotes);
+			tokens.retain(|token| {
+				!excluded
+					.iter()
+					.any(|range| range.start < token.span.end && token.span.start < range.end)
+			});
+		}
+		tokens
+	}
+	/// Applies whichever rule matches the link, or, if the link was resolved, fixes where it leads. If no rule matches, cleans the link up, and if that turned it into a link a rule matches (like by unwrapping a redirect), applies that rule.
+	fn fix_link<'l>(
+		&self,
+		token: LinkToken<'l>,
+		was_message: bool,
+		resolutions: Option<&Resolutions>,
+		settings: &GuildSettings,
+	) -> Option<LinkFix<'l>> {
+		if let Some(resolved) = resolutions.and_then(|resolutions| resolutions.get(token.link)) {
+			return Some(self.fix_resolved(token, resolved, was_message, settings));
+		}
+		if let Some(captures) = self.megapattern.captures(token.link) {
+			return LinkFix::new(token, &captures, self, was_message, settings);
+		}
+		let cleaned = self.tracking.clean(token.link)?;
+		match self.megapattern.captures(&cleaned) {
+			Some(captures) => LinkFix::new(token, &captures, self, was_message, settings),
+			None => Some(LinkFix::without_tracking(token, cleaned, was_message)),
+		}
+	}
+	/// Fixes where a link led as if that had been posted instead. If there is nothing to fix about that, it is the fix itself.
+	fn fix_resolved<'l>(
+		&self,
+		token: LinkToken<'l>,
+		resolved: &str,
+		was_message: bool,
+		settings: &GuildSettings,
+	) -> LinkFix<'l> {
+		let resolved_token = LinkToken {
+			link: resolved,
+			..token.clone()
+		};
+		match self.fix_link(resolved_token, was_message, None, settings) {
+			Some(fix) => LinkFix {
+				rule: fix.rule,
+				link: token.link,
+				span: token.span,
+				fixed: fix.fixed,
+				remove_embed: fix.remove_embed,
+				mirror: fix.mirror,
+			},
+			None => LinkFix::without_tracking(token, resolved.to_string(), was_message),
+		}
 	}
 }
 
+/// Where links led, for the links that were resolved.
+type Resolutions = HashMap<String, String>;
+
+/// Finds the rule whose pattern matched, with its index among the loaded rules and the index of its first capture group in the megapattern.
+fn matched_rule<'r>(
+	captures: &Captures<'_>,
+	replacements: &'r [ReplacementRule],
+) -> (usize, &'r ReplacementRule, usize) {
+	// Every pattern is wrapped in a group, so exactly one of those will have matched, even if the pattern's own groups did not.
+	let mut offset = 1;
+	replacements
+		.iter()
+		.enumerate()
+		.find_map(|(rule, replacement)| {
+			let found = captures
+				.get(offset)
+				.map(|_| (rule, replacement, offset + 1));
+			offset += replacement.capture_group_count + 1;
+			found
+		})
+		.unwrap() // One of the replacements must have matched.
+}
+
 #[derive(Debug)]
 pub struct LinkFix<'l> {
+	/// The index of the rule that fixed the link, among the loaded rules. `None` if only tracking parameters were removed.
+	pub rule: Option<usize>,
+	/// The link as found, without any `<>`.
 	pub link: &'l str,
+	/// Where the link was found in the text, in bytes, including any `<>`.
+	pub span: Range<usize>,
 	pub fixed: String,
 	pub remove_embed: bool,
+	/// The link on a mirror the link was replaced with, if a rule fixed it.
+	pub mirror: Option<MirroredLink>,
+}
+
+/// A link replaced with one on a mirror.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredLink {
+	pub host: String,
+	/// The new link, without any Markdown around it.
+	pub link: String,
 }
 
 impl<'l> LinkFix<'l> {
+	/// Applies the rule that matched, unless the settings disable it. The captures can be of a cleaned-up version of the link.
 	fn new(
-		captures: Captures<'l>,
-		replacements: &[ReplacementRule],
+		token: LinkToken<'l>,
+		captures: &Captures<'_>,
+		link_fixer: &LinkFixer,
 		was_message: bool,
+		settings: &GuildSettings,
 	) -> Option<Self> {
-		let index = captures
-			.iter()
-			.skip(1)
-			.position(|group| group.is_some())
-			.unwrap(); // If it matched the outer regex, it needs to match some group, because all subsections have groups.
-
-		let mut offset = 0;
-		let replacement = replacements
-			.iter()
-			.find(|replacement| {
-				if (offset..offset + replacement.capture_group_count * 2).contains(&index) {
-					true
-				} else {
-					offset += replacement.capture_group_count * 2;
-					false
-				}
-			})
-			.unwrap(); // One of the replacements must match the capture group found.
+		let (rule, replacement, first_group) = matched_rule(captures, &link_fixer.replacements);
+		let name = &replacement.location.name;
+		if !settings.rule_enabled(name) {
+			return None;
+		}
+		if replacement.resolve && !replacement.has_replacement() {
+			// It only makes sense once resolved.
+			return None;
+		}
+		let embed_suppressed = token.suppressed;
 
-		// Whether it found the first version (with `<>`) or the second (without).
-		let embed_suppressed = (offset..offset + replacement.capture_group_count).contains(&index);
 		if was_message
 			&& embed_suppressed
 			&& matches!(replacement.embed_handling, EmbedHandling::Replace)
@@ -86,25 +371,54 @@ impl<'l> LinkFix<'l> {
 			// Replacing the embed from a message is presumed to be the point, but the original was embed suppressed.
 			return None;
 		}
-		if !embed_suppressed {
-			offset += replacement.capture_group_count;
-		}
-		let mut fixed = replacement.apply(&captures, offset);
+		let mut fixed = replacement.apply(
+			captures,
+			first_group,
+			&link_fixer.mirrors,
+			settings.preferred_mirror(name),
+		);
+		let mirror = host_of(&fixed).map(|host| MirroredLink {
+			host: host.to_string(),
+			link: fixed.clone(),
+		});
 
 		if embed_suppressed
 			|| was_message && matches!(replacement.embed_handling, EmbedHandling::DoNothing)
 		{
 			fixed = format!("<{fixed}>");
 		}
+		let fixed = token.rewrap(fixed);
 
 		let fix = Self {
-			link: captures.get(0).unwrap().as_str(),
-			fixed,
+			rule: Some(rule),
+			// Never suppress the original's embeds over a spoilered link.
 			remove_embed: matches!(replacement.embed_handling, EmbedHandling::Replace)
-				&& !embed_suppressed,
+				&& !embed_suppressed
+				&& !token.spoiler,
+			link: token.link,
+			span: token.span,
+			fixed,
+			mirror,
 		};
 		Some(fix)
 	}
+	/// Treats the link as if a rule with embed handling "do nothing" had removed its tracking parameters.
+	fn without_tracking(token: LinkToken<'l>, cleaned: String, was_message: bool) -> Self {
+		let fixed = if token.suppressed || was_message {
+			format!("<{cleaned}>")
+		} else {
+			cleaned
+		};
+		let fixed = token.rewrap(fixed);
+		Self {
+			rule: None,
+			link: token.link,
+			span: token.span,
+			fixed,
+			remove_embed: false,
+			mirror: None,
+		}
+	}
 }
 
 /// How to handle the existing embed and the new link.
@@ -119,14 +433,63 @@ enum EmbedHandling {
 }
 
 impl EmbedHandling {
-	/// # Panics
-	///
-	/// Panics if the config file did not have a valid embed handling mode.
-	fn from_string(string: &str) -> Self {
+	fn from_string(string: &str) -> Option<Self> {
 		match string {
-			"replace" => EmbedHandling::Replace,
-			"do nothing" => EmbedHandling::DoNothing,
-			_ => panic!("The only options for embed handling are \"replace\" and \"do nothing\"."),
+			"replace" => Some(EmbedHandling::Replace),
+			"do nothing" => Some(EmbedHandling::DoNothing),
+			_ => None,
+		}
+	}
+}
+
+/// How long to wait for a message and the bot's reply to both get their embeds, unless the rule file says otherwise.
+pub const DEFAULT_PAIRING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// The config as written, before anything is compiled or checked.
+#[derive(Debug, Default)]
+pub struct ConfigDefinition {
+	pub rules: Vec<RuleDefinition>,
+	/// Query parameters to remove from links that no rule handles.
+	pub tracking_parameters: Vec<String>,
+	/// The path of a ClearURLs database to clean links with.
+	pub clearurls: Option<String>,
+	/// Whether to fix links in block quotes in messages.
+	pub fix_in_quotes: bool,
+	pub mirrors: Vec<MirrorDefinition>,
+	/// How long to wait for a message and the bot's reply to both get their embeds.
+	pub pairing_timeout: Option<Duration>,
+}
+
+/// A mirror to check the health of, by requesting a link on it that should work.
+#[derive(Debug, Clone)]
+pub struct MirrorDefinition {
+	pub host: String,
+	pub canary: String,
+}
+
+/// A rule as written in the config, before anything is compiled or checked.
+#[derive(Debug)]
+pub struct RuleDefinition {
+	/// The position of the rule in the config, counting disabled ones.
+	pub index: usize,
+	pub name: String,
+	pub pattern: String,
+	/// In order of preference. Only rules that resolve links can have none.
+	pub replacements: Vec<String>,
+	pub embed_handling: String,
+	/// Whether to follow the link's redirects and fix where it leads.
+	pub resolve: bool,
+	/// The line in the config the rule was found on, for error messages.
+	pub line: usize,
+	pub examples: Vec<RuleExample>,
+}
+
+impl RuleDefinition {
+	fn location(&self) -> RuleLocation {
+		RuleLocation {
+			index: self.index,
+			name: self.name.clone(),
+			line: self.line,
 		}
 	}
 }
@@ -134,166 +497,349 @@ impl EmbedHandling {
 /// Information about what to replace with what.
 #[derive(Debug)]
 pub struct ReplacementRule {
-	/// The regex pattern (not made into an actual `Regex`) to match and capture parts of.
+	location: RuleLocation,
+	/// The regex pattern (not made into an actual `Regex`) to match and capture parts of, with the names taken off its capture groups, because names need to be unique within the megapattern.
 	///
 	/// This doesn't really need to exist past start-up.
 	pattern: String,
 	/// The number of capture groups is used for finding which capture group of the megapattern belongs to which `ReplacementRule`.
 	capture_group_count: usize,
-	/// The string parts that the captured substrings go between.
-	replacement: Vec<String>,
-	/// Which captured substring goes where.
-	insertion_points: Vec<usize>,
+	/// In order of preference. Later ones are used while the mirrors of earlier ones are down.
+	templates: Vec<Template>,
 	embed_handling: EmbedHandling,
+	/// Whether to follow the link's redirects and fix where it leads instead, with the replacement only as a fallback.
+	resolve: bool,
+	/// Inputs with their expected outputs, checked when the rules are loaded.
+	examples: Vec<RuleExample>,
 }
 
 impl ReplacementRule {
-	/// # Panics
-	///
-	/// Panics on malformed config.
+	/// Checks everything about the rule it can, and returns all the problems found.
 	fn from_config(
-		pattern: &str,
-		replacement: &str,
-		embed_handling: &str,
+		rule: &RuleDefinition,
 		insertion_point_regex: &Regex,
-	) -> Self {
-		let regex = Regex::new(pattern).unwrap();
+	) -> Result<Self, Vec<ConfigError>> {
+		let mut errors = Vec::new();
+		let embed_handling = EmbedHandling::from_string(&rule.embed_handling);
+		if embed_handling.is_none() {
+			errors.push(ConfigError::UnknownEmbedHandling {
+				rule: rule.location(),
+				mode: rule.embed_handling.clone(),
+			});
+		}
+		let regex = match Regex::new(&rule.pattern) {
+			Ok(regex) => regex,
+			Err(error) => {
+				errors.push(ConfigError::InvalidPattern {
+					rule: rule.location(),
+					error,
+				});
+				return Err(errors);
+			}
+		};
 		let capture_group_count = regex.captures_len() - 1;
-		assert!(
-			capture_group_count > 0,
-			"Every pattern needs a capture group."
-		);
-		let embed_handling = EmbedHandling::from_string(embed_handling);
+		if rule.replacements.is_empty() && !rule.resolve {
+			errors.push(ConfigError::MissingReplacement {
+				rule: rule.location(),
+			});
+		}
 
-		let (replacement, insertion_points) =
-			process_replacement(replacement, capture_group_count, insertion_point_regex);
+		let templates = rule
+			.replacements
+			.iter()
+			.map(|replacement| {
+				process_replacement(
+					rule,
+					replacement,
+					&regex,
+					insertion_point_regex,
+					&mut errors,
+				)
+			})
+			.collect();
 
-		assert!(
-			capture_group_count == replacement.len() - 1,
-			"Number of capture groups ({}) does not match number of insertion points in the replacement string ({}) on pattern \"{}\".",
-			capture_group_count,
-			replacement.len() - 1,
-			pattern
-		);
+		match embed_handling {
+			Some(embed_handling) if errors.is_empty() => Ok(Self {
+				location: rule.location(),
+				pattern: remove_group_names(&rule.pattern),
+				capture_group_count,
+				templates,
+				embed_handling,
+				resolve: rule.resolve,
+				examples: rule.examples.clone(),
+			}),
+			_ => Err(errors),
+		}
+	}
+	/// Whether there is a replacement to fall back on, which only rules that resolve links can do without.
+	fn has_replacement(&self) -> bool {
+		!self.templates.is_empty()
+	}
+	/// Fills in the replacement using the preferred mirror, if any and it is not down, or otherwise the first one whose mirror is not down, or the first one if they all are.
+	fn apply(
+		&self,
+		captures: &Captures<'_>,
+		first_group: usize,
+		mirrors: &MirrorStatus,
+		preferred: Option<&str>,
+	) -> String {
+		let mut outputs = self
+			.templates
+			.iter()
+			.map(|template| template.apply(captures, first_group));
+		if let Some(preferred) = preferred
+			&& let Some(output) = outputs.clone().find(|output| {
+				host_of(output).is_some_and(|host| {
+					host.eq_ignore_ascii_case(preferred) && !mirrors.is_down(host)
+				})
+			}) {
+			return output;
+		}
+		let first = outputs.next().unwrap_or_default();
+		if !host_of(&first).is_some_and(|host| mirrors.is_down(host)) {
+			return first;
+		}
+		outputs
+			.find(|output| !host_of(output).is_some_and(|host| mirrors.is_down(host)))
+			.unwrap_or(first)
+	}
+}
 
-		assert!(
-			is_contiguous_starting_at_zero(&insertion_points),
-			"Insertion points need to start at 0 and not skip any numbers. Insertion points were: {:?}",
-			insertion_points
-		);
+/// One replacement of a rule.
+#[derive(Debug)]
+struct Template {
+	/// The string parts that the captured substrings go between.
+	parts: Vec<String>,
+	/// Which captured substring goes where. Groups can be used more than once, or not at all.
+	insertion_points: Vec<InsertionPoint>,
+}
 
-		Self {
-			pattern: pattern.to_string(),
-			capture_group_count,
-			replacement,
-			insertion_points,
-			embed_handling,
+impl Template {
+	/// The host the replacement links to, unless part of it comes from the link.
+	fn host(&self) -> Option<&str> {
+		let start = self.parts.first()?;
+		let (_, rest) = start.split_once("://")?;
+		if !self.insertion_points.is_empty() && !rest.contains(['/', '?', '#']) {
+			return None;
 		}
+		host_of(start)
 	}
-	#[allow(unstable_name_collisions)]
-	fn apply(&self, captures: &Captures<'_>, offset: usize) -> String {
+	/// Fills in the replacement, with `first_group` being the index of this rule's first capture group in the megapattern. Groups that did not participate in the match are left empty.
+	fn apply(&self, captures: &Captures<'_>, first_group: usize) -> String {
 		let mut output = String::new();
 		let mut insertion_iter = self.insertion_points.iter();
-		for part in self
-			.replacement
-			.iter()
-			.map(String::as_str)
-			.intersperse_with(|| &captures[1 + offset + insertion_iter.next().unwrap()])
-		{
+		for part in &self.parts {
 			output.push_str(part);
+			if let Some(point) = insertion_iter.next() {
+				let captured = captures
+					.get(first_group + point.group)
+					.map_or("", |capture| capture.as_str());
+				let filtered = point
+					.filters
+					.iter()
+					.fold(captured.to_string(), |value, filter| filter.apply(value));
+				output.push_str(&filtered);
+			}
 		}
 		output
 	}
 }
 
+/// A place in the replacement where a captured substring goes.
+#[derive(Debug)]
+struct InsertionPoint {
+	/// The 0-based index of the capture group.
+	group: usize,
+	/// Applied to the captured substring in order.
+	filters: Vec<Filter>,
+}
+
+/// Splits the replacement into the parts between placeholders, and what goes between them. Placeholders are either the 0-based index of a group (`{0}`) or its name (`{user}`), optionally followed by filters (`{user|lower|default:someone}`). Problems with placeholders are added to `errors`.
 fn process_replacement(
+	rule: &RuleDefinition,
 	replacement: &str,
-	capture_group_count: usize,
+	regex: &Regex,
 	insertion_point_regex: &Regex,
-) -> (Vec<String>, Vec<usize>) {
+	errors: &mut Vec<ConfigError>,
+) -> Template {
+	let capture_group_count = regex.captures_len() - 1;
 	let mut replacement_parts = Vec::with_capacity(capture_group_count + 1);
 	let mut insertion_points = Vec::with_capacity(capture_group_count);
 	let mut prev_index = 0;
-	for point in insertion_point_regex.find_iter(replacement) {
+	for captures in insertion_point_regex.captures_iter(replacement) {
+		let point = captures.get(0).unwrap();
 		let part = &replacement[prev_index..point.range().start];
 		replacement_parts.push(part.to_string());
 		prev_index = point.range().end;
-		let str = point.as_str();
-		let point = str[1..str.len() - 1].parse::<usize>().unwrap();
-		insertion_points.push(point);
+
+		let mut placeholder = captures[1].split('|');
+		let group_reference = placeholder.next().unwrap().trim();
+		let group = match group_reference.parse::<usize>() {
+			Ok(index) => (index < capture_group_count).then_some(index),
+			Err(_) => regex
+				.capture_names()
+				.position(|name| name == Some(group_reference))
+				.map(|index| index - 1),
+		};
+		let filters = placeholder
+			.filter_map(|filter| {
+				let parsed = Filter::from_string(filter.trim());
+				if parsed.is_none() {
+					errors.push(ConfigError::UnknownFilter {
+						rule: rule.location(),
+						filter: filter.trim().to_string(),
+					});
+				}
+				parsed
+			})
+			.collect();
+		match group {
+			Some(group) => insertion_points.push(InsertionPoint { group, filters }),
+			None => errors.push(ConfigError::UnknownCaptureGroup {
+				rule: rule.location(),
+				placeholder: group_reference.to_string(),
+			}),
+		}
 	}
 	replacement_parts.push(replacement[prev_index..].to_string());
-	(replacement_parts, insertion_points)
+	Template {
+		parts: replacement_parts,
+		insertion_points,
+	}
 }
 
-fn process_replacement_rules(config: &str) -> Vec<ReplacementRule> {
-	let insertion_point_regex = Regex::new(r"\{\d+}").unwrap();
+/// Finds the parts of named capture groups that give them their name, like `?P<user>` in `(?P<user>[a-z]+)`.
+struct GroupNames(Vec<Range<usize>>);
 
-	let mut lines = config.lines();
-	let mut replacements = Vec::new();
+impl ast::Visitor for GroupNames {
+	type Output = Vec<Range<usize>>;
+	type Err = ();
 
-	while let Some(pattern) = lines.next() {
-		let replacement = lines.next().unwrap();
-		let embed_handling = lines.next().unwrap();
-		replacements.push(ReplacementRule::from_config(
-			pattern,
-			replacement,
-			embed_handling,
-			&insertion_point_regex,
-		));
-		if let Some(line) = lines.next()
-			&& !line.is_empty()
+	fn finish(self) -> Result<Self::Output, Self::Err> {
+		Ok(self.0)
+	}
+	fn visit_pre(&mut self, ast: &Ast) -> Result<(), Self::Err> {
+		if let Ast::Group(group) = ast
+			&& let GroupKind::CaptureName { name, .. } = &group.kind
 		{
-			panic!("Expected line to be empty, but found \"{}\".", line);
+			// From just after the `(` up to and including the `>`.
+			self.0
+				.push(group.span.start.offset + 1..name.span.end.offset + 1);
 		}
+		Ok(())
 	}
+}
 
-	replacements
+/// Turns named capture groups into unnamed ones, which keeps the numbering of the groups the same.
+fn remove_group_names(pattern: &str) -> String {
+	let Ok(ast) = ast::parse::Parser::new().parse(pattern) else {
+		// The pattern was already checked, so this should not happen.
+		return pattern.to_string();
+	};
+	let names = ast::visit(&ast, GroupNames(Vec::new())).unwrap_or_default();
+	let mut pattern = pattern.to_string();
+	for range in names.into_iter().rev() {
+		pattern.replace_range(range, "");
+	}
+	pattern
 }
 
-fn make_megapattern(replacements: &[ReplacementRule]) -> Regex {
-	let inner = replacements
-		.iter()
-		.flat_map(|replacement| {
-			[
-				format!("<{}>", replacement.pattern),
-				replacement.pattern.clone(),
-			]
+/// Reads the rules from either a structured rule file or the older line-based format, without compiling or checking them.
+pub fn parse_config(config: &str) -> Result<ConfigDefinition, ConfigError> {
+	if is_rule_file(config) {
+		parse_rule_file(config)
+	} else {
+		Ok(ConfigDefinition {
+			rules: parse_legacy_rules(config)?,
+			..Default::default()
 		})
-		.join("|");
-	Regex::new(&format!("(?i)^(?:{inner})$")).unwrap()
+	}
 }
 
-fn is_contiguous_starting_at_zero(list: &[usize]) -> bool {
-	let mut found_values = vec![false; list.len()];
-	for number in list {
-		let Some(was_found) = found_values.get_mut(*number) else {
-			return false;
+/// Reads the older line-based format: pattern, replacement and embed handling mode on consecutive lines, with rules separated by empty lines.
+pub fn parse_legacy_rules(config: &str) -> Result<Vec<RuleDefinition>, ConfigError> {
+	let mut lines = config.lines().enumerate();
+	let mut rules = Vec::new();
+
+	while let Some((line_index, pattern)) = lines.next() {
+		let (Some((_, replacement)), Some((_, embed_handling))) = (lines.next(), lines.next())
+		else {
+			return Err(ConfigError::TruncatedRule {
+				index: rules.len(),
+				line: line_index + 1,
+			});
 		};
-		*was_found = true;
+		rules.push(RuleDefinition {
+			index: rules.len(),
+			name: format!("rule-{}", rules.len() + 1),
+			pattern: pattern.to_string(),
+			replacements: vec![replacement.to_string()],
+			embed_handling: embed_handling.to_string(),
+			resolve: false,
+			line: line_index + 1,
+			examples: Vec::new(),
+		});
+		if let Some((empty_line_index, line)) = lines.next()
+			&& !line.is_empty()
+		{
+			return Err(ConfigError::ExpectedEmptyLine {
+				index: rules.len() - 1,
+				line: empty_line_index + 1,
+				found: line.to_string(),
+			});
+		}
 	}
-	found_values.into_iter().all(std::convert::identity)
+
+	Ok(rules)
+}
+
+fn make_megapattern(replacements: &[ReplacementRule]) -> Result<Regex, ConfigError> {
+	let inner = replacements
+		.iter()
+		.map(|replacement| format!("({})", replacement.pattern))
+		.join("|");
+	Regex::new(&format!("(?i)^(?:{inner})$")).map_err(ConfigError::Megapattern)
 }
 
 #[cfg(test)]
 mod tests {
+	use std::collections::HashSet;
+
 	use super::*;
+	use crate::mirrors::CheckSource;
+
+	/// The replacement of the X rule most tests use.
+	const FIXUPX: &str = r#""https://fixupx.com/{0}""#;
+	/// The same with a fallback mirror.
+	const FIXUPX_OR_VXTWITTER: &str = r#"["https://fixupx.com/{0}", "https://vxtwitter.com/{0}"]"#;
+
+	/// The rule for X links most tests use, with the replacement as TOML, to go after any other settings.
+	fn x_rule(replacement: &str) -> String {
+		format!(
+			r#"
+[[rule]]
+name = "x"
+pattern = 'https://x\.com/([0-9a-z_]+/status/[0-9]+)\S*'
+replacement = {replacement}
+"#
+		)
+	}
 
 	#[test]
 	fn find_instagram() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = "blahblah https://www.instagram.com/reel/abc blahblah";
 		let find = link_fixer.find_and_fix(string).next();
 		assert_eq!(
 			find.map(|fix| fix.fixed),
-			Some(String::from("https://www.instagramez.com/reel/abc/"))
+			Some(String::from("https://www.eeinstagram.com/reel/abc/"))
 		);
 	}
 	#[test]
 	fn find_reddit() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = "blahblah https://www.reddit.com/r/fictitious/comments/abc/dëf blahblah";
 		let find = link_fixer.find_and_fix(string).next();
 		assert_eq!(
@@ -305,8 +851,8 @@ mod tests {
 	}
 	#[test]
 	fn find_twitter() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = "blahblah https://x.com/fictitious/status/0123 blahblah";
 		let find = link_fixer.find_and_fix(string).next();
 		assert_eq!(
@@ -316,8 +862,8 @@ mod tests {
 	}
 	#[test]
 	fn find_youtube() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = "blahblah https://www.youtube.com/shorts/GX5wEDmbpQA blahblah";
 		let find = link_fixer.find_and_fix(string).next();
 		assert_eq!(
@@ -329,10 +875,10 @@ mod tests {
 	}
 	#[test]
 	fn find_amazon() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = "https://www.amazon.ca/Some-Item-With-Code-ABC012/dp/ABC012?all_sorts_of=tracking.data&other_random=bs&believability_of_the_volume=false";
-		let find = link_fixer.find_and_fix(&string).next();
+		let find = link_fixer.find_and_fix(string).next();
 		assert_eq!(
 			find.map(|fix| fix.fixed),
 			Some(String::from("<https://www.amazon.ca/dp/ABC012>"))
@@ -340,17 +886,17 @@ mod tests {
 	}
 	#[test]
 	fn find_each() {
-		let config = std::fs::read_to_string("./replacements.txt").unwrap();
-		let link_fixer = LinkFixer::from_config(&config);
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
 		let string = r"hey <https://www.amazon.ca/Some-Item-With-Code-ABC012/dp/ABC012?all_sorts_of=tracking.data&other_random=bs&believability_of_the_volume=false> and https://www.instagram.com/reel/abc blahblah https://www.reddit.com/r/fictitious/comments/abc/def https://x.com/fictitious/status/0123 and https://www.youtube.com/shorts/GX5wEDmbpQA";
-		let mut links = link_fixer.find_and_fix(&string);
+		let mut links = link_fixer.find_and_fix(string);
 		assert_eq!(
 			links.next().map(|fix| fix.fixed),
 			Some(String::from("<https://www.amazon.ca/dp/ABC012>"))
 		);
 		assert_eq!(
 			links.next().map(|fix| fix.fixed),
-			Some(String::from("https://www.instagramez.com/reel/abc/"))
+			Some(String::from("https://www.eeinstagram.com/reel/abc/"))
 		);
 		assert_eq!(
 			links.next().map(|fix| fix.fixed),
@@ -369,4 +915,460 @@ mod tests {
 			))
 		);
 	}
+	#[test]
+	fn legacy_format() {
+		let config = "https://x\\.com/([0-9a-z_]+/status/[0-9]+)\\S*\nhttps://fixupx.com/{0}\nreplace\n\nhttps://(?:www\\.)?youtube\\.com/shorts/([-0-9a-z_]+)\\S*\nhttps://www.youtube.com/watch?v={0}\ndo nothing";
+		let link_fixer = LinkFixer::from_config(config).unwrap();
+		let string = "https://x.com/fictitious/status/0123 https://youtube.com/shorts/GX5wEDmbpQA";
+		let mut links = link_fixer.find_and_fix(string);
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from("https://fixupx.com/fictitious/status/0123"))
+		);
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from(
+				"<https://www.youtube.com/watch?v=GX5wEDmbpQA>"
+			))
+		);
+	}
+	#[test]
+	fn convert_legacy_format() {
+		let config = "https://x\\.com/([0-9a-z_]+/status/[0-9]+)\\S*\nhttps://fixupx.com/{0}\nreplace\n\nhttps://www\\.amazon\\.(com|ca)/[^\\s/]+/dp/([A-Z0-9]+)\\S*\nhttps://www.amazon.{0}/dp/{1}\ndo nothing\n";
+		let converted = crate::rule_file::convert_legacy(config).unwrap();
+		assert!(is_rule_file(&converted));
+		let legacy = LinkFixer::from_config(config).unwrap();
+		let structured = LinkFixer::from_config(&converted).unwrap();
+		let string = "https://x.com/fictitious/status/0123 https://www.amazon.ca/Item/dp/ABC012?tracking=yes";
+		assert_eq!(
+			legacy
+				.find_and_fix(string)
+				.map(|fix| fix.fixed)
+				.collect::<Vec<_>>(),
+			structured
+				.find_and_fix(string)
+				.map(|fix| fix.fixed)
+				.collect::<Vec<_>>()
+		);
+	}
+	#[test]
+	fn report_every_problem() {
+		let config = r#"
+[[rule]]
+name = "bad pattern"
+pattern = 'https://x\.com/([0-9a-z_]+'
+replacement = "https://fixupx.com/{0}"
+
+[[rule]]
+name = "unknown placeholder"
+pattern = 'https://x\.com/(?P<user>[0-9a-z_]+)/status/([0-9]+)'
+replacement = "https://fixupx.com/{username}/status/{1}"
+embed = "remove"
+"#;
+		let errors = LinkFixer::from_config(config).err().unwrap();
+		assert!(matches!(
+			&errors[..],
+			[
+				ConfigError::InvalidPattern { rule: first, .. },
+				ConfigError::UnknownEmbedHandling { rule: second, .. },
+				ConfigError::UnknownCaptureGroup {
+					rule: third,
+					placeholder,
+				},
+			] if first.line == 4 && second.index == 1 && third.line == 9 && placeholder == "username"
+		));
+	}
+	#[test]
+	fn report_truncated_rule() {
+		let config = "https://x\\.com/([0-9a-z_]+/status/[0-9]+)\\S*\nhttps://fixupx.com/{0}\nreplace\n\nhttps://redd\\.it/([0-9a-z]+)/?\\S*\nhttps://rxddit.com/{0}";
+		let errors = LinkFixer::from_config(config).err().unwrap();
+		assert!(matches!(
+			&errors[..],
+			[ConfigError::TruncatedRule { index: 1, line: 5 }]
+		));
+	}
+	#[test]
+	fn rule_file_examples() {
+		let config = std::fs::read_to_string("./replacements.toml").unwrap();
+		let link_fixer = LinkFixer::compile(&config).unwrap();
+		for (index, rule) in link_fixer.replacements.iter().enumerate() {
+			assert!(
+				!rule.examples.is_empty(),
+				"{} has no examples.",
+				rule.location
+			);
+			let errors = link_fixer.example_errors(index);
+			assert!(
+				errors.is_empty(),
+				"{}",
+				errors.iter().map(ToString::to_string).join("\n")
+			);
+		}
+	}
+	#[test]
+	fn reject_failing_example() {
+		let config = format!(
+			r#"{}examples = [{{ input = "https://x.com/a/status/1", slash = "https://fxtwitter.com/a/status/1" }}]"#,
+			x_rule(FIXUPX)
+		);
+		let errors = LinkFixer::from_config(&config).err().unwrap();
+		assert!(matches!(
+			&errors[..],
+			[ConfigError::ExampleMismatch {
+				mode: ExampleMode::Slash,
+				output,
+				..
+			}] if output == "https://fixupx.com/a/status/1"
+		));
+	}
+	#[test]
+	fn named_and_optional_captures() {
+		let config = r#"
+[[rule]]
+name = "optional only"
+pattern = 'https://example\.com/(?:(a)|(b))?'
+replacement = "https://example.org/{0}{1}"
+
+[[rule]]
+name = "named"
+pattern = 'https://(www\.)?x\.com/(?P<user>[0-9a-z_]+)/status/(?P<id>[0-9]+)(/photo/[0-9])?'
+replacement = "https://fixupx.com/{user}/status/{id}{3} ({user})"
+"#;
+		let link_fixer = LinkFixer::from_config(config).unwrap();
+		let string = "https://example.com/ https://x.com/fictitious/status/0123 https://www.x.com/fictitious/status/0123/photo/1";
+		let mut links = link_fixer.find_and_fix(string);
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from("https://example.org/"))
+		);
+		assert_eq!(
+			links.next().map(|fix| (fix.rule, fix.fixed)),
+			Some((
+				Some(1),
+				String::from("https://fixupx.com/fictitious/status/0123 (fictitious)")
+			))
+		);
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from(
+				"https://fixupx.com/fictitious/status/0123/photo/1 (fictitious)"
+			))
+		);
+	}
+	#[test]
+	fn filters() {
+		let config = r#"
+[[rule]]
+name = "filtered"
+pattern = 'https://example\.com/(?:(?P<a>[a-z]+)\.)?(?P<b>\S*)'
+replacement = "https://example.org/{a|default:none}/{b|urldecode|trim_slash|lower}?q={b|urlencode}"
+"#;
+		let link_fixer = LinkFixer::from_config(config).unwrap();
+		let mut links =
+			link_fixer.find_and_fix("https://example.com/Some%20Page/ https://example.com/x.Y");
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from(
+				"https://example.org/none/some page?q=Some%2520Page%2F"
+			))
+		);
+		assert_eq!(
+			links.next().map(|fix| fix.fixed),
+			Some(String::from("https://example.org/x/y?q=Y"))
+		);
+		let config = config.replace("trim_slash", "trim");
+		let errors = LinkFixer::from_config(&config).err().unwrap();
+		assert!(matches!(
+			&errors[..],
+			[ConfigError::UnknownFilter { filter, .. }] if filter == "trim"
+		));
+	}
+	#[test]
+	fn masked_links() {
+		let config = format!("[tracking]\nparameters = [\"si\"]\n{}", x_rule(FIXUPX));
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let string = "[my post](https://x.com/a/status/1?s=20) [old](<https://x.com/b/status/2>) [page](https://example.com/?si=abc)";
+		let fixes = link_fixer.find_and_fix(string).collect::<Vec<_>>();
+		assert_eq!(
+			fixes
+				.iter()
+				.map(|fix| (fix.link, fix.fixed.as_str(), fix.remove_embed))
+				.collect::<Vec<_>>(),
+			[
+				(
+					"https://x.com/a/status/1?s=20",
+					"[my post](https://fixupx.com/a/status/1)",
+					true
+				),
+				(
+					"https://example.com/?si=abc",
+					"[page](<https://example.com/>)",
+					false
+				),
+			]
+		);
+		assert_eq!(
+			link_fixer
+				.find_and_fix_slash(string)
+				.map(|fix| fix.fixed)
+				.collect::<Vec<_>>(),
+			[
+				"[my post](https://fixupx.com/a/status/1)",
+				"[old](<https://fixupx.com/b/status/2>)",
+				"[page](https://example.com/)",
+			]
+		);
+	}
+	#[test]
+	fn skip_code_and_quotes() {
+		let config = x_rule(FIXUPX);
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let string = "`https://x.com/a/status/1`\n```\nhttps://x.com/b/status/2\n```\n> https://x.com/c/status/3\nhttps://x.com/d/status/4";
+		let fixed =
+			|fixes: Vec<LinkFix>| fixes.into_iter().map(|fix| fix.fixed).collect::<Vec<_>>();
+		assert_eq!(
+			fixed(link_fixer.find_and_fix(string).collect()),
+			["https://fixupx.com/d/status/4"]
+		);
+		assert_eq!(
+			fixed(link_fixer.find_and_fix_slash(string).collect()).len(),
+			4
+		);
+
+		let link_fixer =
+			LinkFixer::from_config(&format!("[markdown]\nfix_in_quotes = true\n{config}")).unwrap();
+		assert_eq!(
+			fixed(link_fixer.find_and_fix(string).collect()),
+			[
+				"https://fixupx.com/c/status/3",
+				"https://fixupx.com/d/status/4"
+			]
+		);
+	}
+	#[test]
+	fn spoilered_links() {
+		let link_fixer = LinkFixer::from_config(&x_rule(FIXUPX)).unwrap();
+		let string = "||ending|| https://x.com/a/status/1 ||https://x.com/b/status/2||";
+		assert_eq!(
+			link_fixer
+				.find_and_fix(string)
+				.map(|fix| (fix.fixed, fix.remove_embed))
+				.collect::<Vec<_>>(),
+			[
+				(String::from("https://fixupx.com/a/status/1"), true),
+				(String::from("||https://fixupx.com/b/status/2||"), false),
+			]
+		);
+	}
+	struct StubResolver(HashMap<&'static str, &'static str>);
+
+	#[serenity::async_trait]
+	impl Resolver for StubResolver {
+		async fn resolve(&self, link: &str) -> Option<String> {
+			self.0.get(link).map(|resolved| resolved.to_string())
+		}
+	}
+
+	#[tokio::test]
+	async fn resolve_links() {
+		let config = format!(
+			r#"{}
+[[rule]]
+name = "short"
+pattern = 'https://short\.example/\S+'
+resolve = true
+
+[[rule]]
+name = "share"
+pattern = 'https://share\.example/(\S+)'
+replacement = "https://share.example/{{0}} (share link)"
+resolve = true
+"#,
+			x_rule(FIXUPX)
+		);
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let resolver = StubResolver(HashMap::from([
+			("https://short.example/a", "https://x.com/a/status/1?s=20"),
+			("https://short.example/b", "https://example.com/page"),
+		]));
+		let string = "https://short.example/a https://short.example/b https://short.example/c https://share.example/d";
+		assert_eq!(
+			link_fixer
+				.find_and_fix_resolving(string, true, &resolver, &GuildSettings::default())
+				.await
+				.into_iter()
+				.map(|fix| (fix.link, fix.fixed))
+				.collect::<Vec<_>>(),
+			[
+				(
+					"https://short.example/a",
+					String::from("https://fixupx.com/a/status/1")
+				),
+				(
+					"https://short.example/b",
+					String::from("<https://example.com/page>")
+				),
+				(
+					"https://share.example/d",
+					String::from("https://share.example/d (share link)")
+				),
+			]
+		);
+
+		let missing_replacement =
+			"[[rule]]\nname = \"short\"\npattern = 'https://short\\.example/\\S+'\n";
+		let errors = LinkFixer::from_config(missing_replacement).err().unwrap();
+		assert!(matches!(
+			&errors[..],
+			[ConfigError::MissingReplacement { .. }]
+		));
+	}
+	#[test]
+	fn mirror_fallback() {
+		let link_fixer = LinkFixer::from_config(&x_rule(FIXUPX_OR_VXTWITTER)).unwrap();
+		assert_eq!(
+			link_fixer.rule_mirrors("x"),
+			Some(vec!["fixupx.com", "vxtwitter.com"])
+		);
+		let fix = |string| link_fixer.find_and_fix(string).next().unwrap();
+		let first = fix("https://x.com/a/status/1");
+		assert_eq!(
+			(
+				first.fixed.as_str(),
+				first.mirror.as_ref().map(|mirror| mirror.host.as_str())
+			),
+			("https://fixupx.com/a/status/1", Some("fixupx.com"))
+		);
+		link_fixer
+			.mirrors()
+			.record("fixupx.com", false, CheckSource::Canary);
+		assert_eq!(
+			fix("https://x.com/a/status/1").fixed,
+			"https://vxtwitter.com/a/status/1"
+		);
+		link_fixer
+			.mirrors()
+			.record("vxtwitter.com", false, CheckSource::Canary);
+		assert_eq!(
+			fix("https://x.com/a/status/1").fixed,
+			"https://fixupx.com/a/status/1"
+		);
+	}
+	#[tokio::test]
+	async fn guild_settings() {
+		let config = format!(
+			r#"{}
+[[rule]]
+name = "instagram"
+pattern = 'https://www\.instagram\.com/(p/[0-9A-Za-z_-]+)/?'
+replacement = "https://www.ddinstagram.com/{{0}}/"
+"#,
+			x_rule(FIXUPX_OR_VXTWITTER)
+		);
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let settings = GuildSettings {
+			disabled_rules: HashSet::from([String::from("instagram")]),
+			mirrors: HashMap::from([(String::from("x"), String::from("vxtwitter.com"))]),
+			..GuildSettings::default()
+		};
+		let fixed = |settings| {
+			let link_fixer = &link_fixer;
+			async move {
+				link_fixer
+					.find_and_fix_resolving(
+						"https://x.com/a/status/1 https://www.instagram.com/p/b/",
+						false,
+						&StubResolver(HashMap::new()),
+						settings,
+					)
+					.await
+					.into_iter()
+					.map(|fix| fix.fixed)
+					.collect::<Vec<_>>()
+			}
+		};
+		assert_eq!(fixed(&settings).await, ["https://vxtwitter.com/a/status/1"]);
+		link_fixer
+			.mirrors()
+			.record("vxtwitter.com", false, CheckSource::Canary);
+		assert_eq!(fixed(&settings).await, ["https://fixupx.com/a/status/1"]);
+		assert_eq!(
+			fixed(&GuildSettings::default()).await,
+			[
+				"https://fixupx.com/a/status/1",
+				"https://www.ddinstagram.com/p/b/"
+			]
+		);
+		assert_eq!(
+			link_fixer.fixable_links(
+				"https://x.com/a/status/1 https://www.instagram.com/p/b/ https://example.com/",
+				&settings
+			),
+			["https://x.com/a/status/1"]
+		);
+	}
+	#[test]
+	fn remove_tracking() {
+		let config = format!(
+			"[tracking]\nparameters = [\"utm_*\", \"fbclid\", \"si\"]\n{}",
+			x_rule(FIXUPX)
+		);
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let string = "https://example.com/page?id=3&utm_source=x&UTM_medium=y#top <https://example.com/?fbclid=abc> https://x.com/a/status/1?si=abc https://example.com/?id=3 example.com/?si=abc";
+		assert_eq!(
+			link_fixer
+				.find_and_fix(string)
+				.map(|fix| fix.fixed)
+				.collect::<Vec<_>>(),
+			[
+				"<https://example.com/page?id=3#top>",
+				"<https://example.com/>",
+				"https://fixupx.com/a/status/1",
+			]
+		);
+		assert_eq!(
+			link_fixer
+				.find_and_fix_slash(string)
+				.map(|fix| fix.fixed)
+				.collect::<Vec<_>>(),
+			[
+				"https://example.com/page?id=3#top",
+				"<https://example.com/>",
+				"https://fixupx.com/a/status/1",
+			]
+		);
+	}
+
+	#[test]
+	fn clearurls_database() {
+		let database = r#"{"providers": {
+			"google": {
+				"urlPattern": "^https?:\\/\\/(?:[a-z0-9-]+\\.)*?google(?:\\.[a-z]{2,}){1,}",
+				"rules": ["ved", "ei"],
+				"rawRules": ["\\/ref=[^\\/?]*"],
+				"exceptions": ["^https?:\\/\\/mail\\.google\\.com"],
+				"redirections": ["^https?:\\/\\/(?:[a-z0-9-]+\\.)*?google(?:\\.[a-z]{2,}){1,}\\/url\\?.*?(?:url|q)=(https?[^&]+)"]
+			}
+		}}"#;
+		let path = std::env::temp_dir().join("linkfixbot-clearurls-test.json");
+		std::fs::write(&path, database).unwrap();
+		let config = format!(
+			"[tracking]\nclearurls = '{}'\n{}",
+			path.display(),
+			x_rule(FIXUPX)
+		);
+		let link_fixer = LinkFixer::from_config(&config).unwrap();
+		let string = "https://www.google.com/url?q=https%3A%2F%2Fx.com%2Fa%2Fstatus%2F1&sa=D https://www.google.com/url?q=https%3A%2F%2Fexample.com%2F%3Fid%3D3 https://www.google.com/search/ref=abc?q=cat&ved=1&ei=2 https://mail.google.com/?ved=1";
+		assert_eq!(
+			link_fixer
+				.find_and_fix_slash(string)
+				.map(|fix| (fix.rule, fix.fixed))
+				.collect::<Vec<_>>(),
+			[
+				(Some(0), String::from("https://fixupx.com/a/status/1")),
+				(None, String::from("https://example.com/?id=3")),
+				(None, String::from("https://www.google.com/search?q=cat")),
+			]
+		);
+	}
 }
@@ -0,0 +1,112 @@
+use std::{
+	fs,
+	sync::Arc,
+	time::{Duration, SystemTime},
+};
+
+use tokio::sync::RwLock;
+
+use crate::{
+	config_error::ConfigError,
+	fix_link::LinkFixer,
+	mirrors::{MIRROR_STATUS_FILE, MirrorStatus},
+};
+
+/// The structured rule file, used if it exists.
+pub const RULE_FILE: &str = "./replacements.toml";
+/// The older line-based rule file, used if there is no structured one.
+pub const LEGACY_RULE_FILE: &str = "./replacements.txt";
+
+/// How often to check whether the rule file changed.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Why the rules could not be (re)loaded.
+pub enum LoadError {
+	/// Neither rule file could be read.
+	Unreadable,
+	Config(Vec<ConfigError>),
+}
+
+/// Reads whichever rule file exists, preferring the structured one.
+pub fn read_rule_file() -> Result<String, LoadError> {
+	fs::read_to_string(RULE_FILE)
+		.or_else(|_| fs::read_to_string(LEGACY_RULE_FILE))
+		.map_err(|_| LoadError::Unreadable)
+}
+
+/// Reads and validates whichever rule file exists, preferring the structured one.
+pub fn load_link_fixer() -> Result<LinkFixer, LoadError> {
+	LinkFixer::from_config(&read_rule_file()?).map_err(LoadError::Config)
+}
+
+/// The `LinkFixer`, behind a lock so the rules can be swapped out while the bot runs.
+pub struct LinkFixerHandle {
+	link_fixer: RwLock<Arc<LinkFixer>>,
+	/// Kept across reloads.
+	mirrors: Arc<MirrorStatus>,
+}
+
+impl LinkFixerHandle {
+	pub fn new(link_fixer: LinkFixer) -> Self {
+		let mirrors = Arc::new(MirrorStatus::load(MIRROR_STATUS_FILE));
+		Self {
+			link_fixer: RwLock::new(Arc::new(link_fixer.with_mirrors(mirrors.clone()))),
+			mirrors,
+		}
+	}
+	pub fn mirrors(&self) -> &MirrorStatus {
+		&self.mirrors
+	}
+	/// The current rules. Holding on to these does not block reloads.
+	pub async fn get(&self) -> Arc<LinkFixer> {
+		self.link_fixer.read().await.clone()
+	}
+	/// Reloads the rules from disk. If they fail to load, the old rules stay in place.
+	pub async fn reload(&self) -> Result<(), LoadError> {
+		let link_fixer = load_link_fixer()?.with_mirrors(self.mirrors.clone());
+		*self.link_fixer.write().await = Arc::new(link_fixer);
+		Ok(())
+	}
+}
+
+/// The last time either rule file was modified.
+fn rule_file_modified() -> Option<SystemTime> {
+	[RULE_FILE, LEGACY_RULE_FILE]
+		.into_iter()
+		.filter_map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+		.max()
+}
+
+/// Reloads the rules whenever a rule file changes, reporting any problems with the new rules.
+pub async fn watch_rule_files(handle: Arc<LinkFixerHandle>) {
+	let mut last_modified = rule_file_modified();
+	loop {
+		tokio::time::sleep(WATCH_INTERVAL).await;
+		let modified = rule_file_modified();
+		if modified == last_modified {
+			continue;
+		}
+		last_modified = modified;
+		match handle.reload().await {
+			Ok(()) => println!("Reloaded the rules after the rule file changed."),
+			Err(error) => {
+				eprintln!("The rule file changed, but I kept the old rules.");
+				print_load_error(&error);
+			}
+		}
+	}
+}
+
+pub fn print_load_error(error: &LoadError) {
+	match error {
+		LoadError::Unreadable => eprintln!("Could not read {RULE_FILE} or {LEGACY_RULE_FILE}."),
+		LoadError::Config(errors) => print_config_errors(errors),
+	}
+}
+
+pub fn print_config_errors(errors: &[ConfigError]) {
+	eprintln!("Found {} problem(s) with the rules:", errors.len());
+	for error in errors {
+		eprintln!("- {error}");
+	}
+}
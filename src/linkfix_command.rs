@@ -0,0 +1,409 @@
+use serenity::all::*;
+
+use crate::{
+	fix_link::LinkFixer,
+	link_fixer_handle::LinkFixerHandle,
+	reply_shortcuts::ReplyShortcuts,
+	settings::{ChannelRule, SettingsStore, Toggle},
+	util::truncate_message,
+};
+
+/// The most choices Discord shows for autocompletion.
+const MAX_CHOICES: usize = 25;
+
+pub async fn handle(
+	context: &Context,
+	interaction: CommandInteraction,
+	link_fixer: &LinkFixerHandle,
+	settings: &SettingsStore,
+) {
+	let options = interaction.data.options();
+	let Some(ResolvedOption { name, value, .. }) = options.first() else {
+		return;
+	};
+	let response = match (*name, value) {
+		("settings", ResolvedValue::SubCommandGroup(options)) => {
+			change_settings(&interaction, options, link_fixer, settings).await
+		}
+		("optout", ResolvedValue::SubCommand(options)) => {
+			Some(set_opted_out(&interaction, options, settings, true).await)
+		}
+		("optin", ResolvedValue::SubCommand(options)) => {
+			Some(set_opted_out(&interaction, options, settings, false).await)
+		}
+		_ => None,
+	};
+	if let Some(response) = response {
+		let _ = interaction
+			.ephemeral_reply(&context.http, truncate_message(response))
+			.await;
+	}
+}
+
+/// Runs a subcommand of `settings`, for members who can manage the server.
+async fn change_settings(
+	interaction: &CommandInteraction,
+	options: &[ResolvedOption<'_>],
+	link_fixer: &LinkFixerHandle,
+	settings: &SettingsStore,
+) -> Option<String> {
+	let Some(guild) = interaction.guild_id else {
+		return Some(String::from("Settings only exist in servers."));
+	};
+	if !interaction
+		.member
+		.as_ref()
+		.and_then(|member| member.permissions)
+		.is_some_and(|permissions| permissions.manage_guild())
+	{
+		return Some(String::from(
+			"You need the Manage Server permission to change my settings.",
+		));
+	}
+	let Some(ResolvedOption {
+		name,
+		value: ResolvedValue::SubCommand(options),
+		..
+	}) = options.first()
+	else {
+		return None;
+	};
+	let link_fixer = link_fixer.get().await;
+	let response = match *name {
+		"show" => show(guild, &link_fixer, settings).await,
+		"enable" => set_rule(guild, options, &link_fixer, settings, true).await,
+		"disable" => set_rule(guild, options, &link_fixer, settings, false).await,
+		"mirror" => set_mirror(guild, options, &link_fixer, settings).await,
+		"channel" => set_channel(guild, options, settings).await,
+		"toggle" => set_toggle(guild, options, settings).await,
+		_ => return None,
+	};
+	Some(response)
+}
+
+/// Stops or resumes automatic replies to the user's messages, in this server or, if asked or in DMs, everywhere. Anyone can do this for themselves.
+async fn set_opted_out(
+	interaction: &CommandInteraction,
+	options: &[ResolvedOption<'_>],
+	settings: &SettingsStore,
+	opted_out: bool,
+) -> String {
+	let everywhere = bool_option(options, "everywhere").unwrap_or(false);
+	let guild = interaction.guild_id.filter(|_| !everywhere);
+	let user = interaction.user.id;
+	let confirmation = match (opted_out, guild) {
+		(true, Some(_)) => {
+			"I will no longer reply to your links in this server on my own. You can still ask me to fix them."
+		}
+		(true, None) => {
+			"I will no longer reply to your links on my own anywhere. You can still ask me to fix them."
+		}
+		(false, Some(_)) if settings.is_opted_out(user, None).await => {
+			"I will reply to your links in this server again, once you also opt back in everywhere."
+		}
+		(false, Some(_)) => "I will reply to your links in this server again.",
+		(false, None) => "I will reply to your links everywhere again.",
+	};
+	saved(
+		settings.set_opted_out(user, guild, opted_out).await,
+		String::from(confirmation),
+	)
+}
+
+/// Suggests the names of the loaded rules, or the mirrors of the chosen rule.
+pub async fn autocomplete(
+	context: &Context,
+	interaction: CommandInteraction,
+	link_fixer: &LinkFixer,
+) {
+	let Some(focused) = interaction.data.autocomplete() else {
+		return;
+	};
+	let typed = focused.value.to_lowercase();
+	let choices: Vec<&str> = match focused.name {
+		"rule" => link_fixer.rule_names().collect(),
+		"host" => subcommand_options(&interaction.data.options())
+			.and_then(|options| string_option(options, "rule"))
+			.and_then(|rule| link_fixer.rule_mirrors(rule))
+			.unwrap_or_default(),
+		_ => return,
+	};
+	let response = choices
+		.into_iter()
+		.filter(|choice| choice.to_lowercase().contains(&typed))
+		.take(MAX_CHOICES)
+		.fold(CreateAutocompleteResponse::new(), |response, choice| {
+			response.add_string_choice(choice, choice)
+		});
+	let _ = interaction
+		.create_response(
+			&context.http,
+			CreateInteractionResponse::Autocomplete(response),
+		)
+		.await;
+}
+
+async fn show(guild: GuildId, link_fixer: &LinkFixer, settings: &SettingsStore) -> String {
+	let settings = settings.get(guild).await;
+	let mut lines = Toggle::ALL
+		.into_iter()
+		.map(|toggle| {
+			let state = if settings.get(toggle) { "on" } else { "off" };
+			format!("{}: {state}", toggle.description())
+		})
+		.collect::<Vec<_>>();
+	if !settings.channels.is_empty() {
+		lines.push(String::from("Channels:"));
+		for (channel, rule) in &settings.channels {
+			let rule = match rule {
+				ChannelRule::Allow => "always fixed automatically",
+				ChannelRule::Deny => "never fixed automatically",
+			};
+			lines.push(format!("- <#{}>: {rule}", channel.get()));
+		}
+	}
+	lines.push(String::from("Rules:"));
+	for rule in link_fixer.rule_names() {
+		let state = if settings.rule_enabled(rule) {
+			'✅'
+		} else {
+			'❌'
+		};
+		match settings.preferred_mirror(rule) {
+			Some(host) => lines.push(format!("{state} {rule} (prefers {host})")),
+			None => lines.push(format!("{state} {rule}")),
+		}
+	}
+	lines.join("\n")
+}
+
+async fn set_rule(
+	guild: GuildId,
+	options: &[ResolvedOption<'_>],
+	link_fixer: &LinkFixer,
+	settings: &SettingsStore,
+	enabled: bool,
+) -> String {
+	let Some(rule) = string_option(options, "rule") else {
+		return String::from("Which rule?");
+	};
+	if !link_fixer.rule_names().any(|name| name == rule) {
+		return format!("There is no rule called {rule}.");
+	}
+	let state = if enabled { "enabled" } else { "disabled" };
+	saved(
+		settings.set_rule_enabled(guild, rule, enabled).await,
+		format!("The rule {rule} is now {state}."),
+	)
+}
+
+async fn set_mirror(
+	guild: GuildId,
+	options: &[ResolvedOption<'_>],
+	link_fixer: &LinkFixer,
+	settings: &SettingsStore,
+) -> String {
+	let Some(rule) = string_option(options, "rule") else {
+		return String::from("Which rule?");
+	};
+	let Some(mirrors) = link_fixer.rule_mirrors(rule) else {
+		return format!("There is no rule called {rule}.");
+	};
+	let Some(host) = string_option(options, "host") else {
+		return saved(
+			settings.set_mirror(guild, rule, None).await,
+			format!("The rule {rule} uses its mirrors in the usual order again."),
+		);
+	};
+	let Some(host) = mirrors
+		.into_iter()
+		.find(|mirror| mirror.eq_ignore_ascii_case(host))
+	else {
+		return format!("The rule {rule} does not use {host}.");
+	};
+	saved(
+		settings.set_mirror(guild, rule, Some(host)).await,
+		format!("The rule {rule} now prefers {host}, while it is up."),
+	)
+}
+
+async fn set_channel(
+	guild: GuildId,
+	options: &[ResolvedOption<'_>],
+	settings: &SettingsStore,
+) -> String {
+	let Some(channel) = options.iter().find_map(|option| match option.value {
+		ResolvedValue::Channel(channel) if option.name == "channel" => Some(channel.id),
+		_ => None,
+	}) else {
+		return String::from("Which channel?");
+	};
+	let mode = string_option(options, "mode").unwrap_or_default();
+	let rule = ChannelRule::from_name(mode);
+	let mention = format!("<#{}>", channel.get());
+	let confirmation = match rule {
+		Some(ChannelRule::Allow) => {
+			format!("Links in {mention} are now always fixed automatically.")
+		}
+		Some(ChannelRule::Deny) => format!("Links in {mention} are now never fixed automatically."),
+		None => format!("{mention} now follows what it is in for automatic fixing."),
+	};
+	saved(
+		settings.set_channel_rule(guild, channel, rule).await,
+		confirmation,
+	)
+}
+
+async fn set_toggle(
+	guild: GuildId,
+	options: &[ResolvedOption<'_>],
+	settings: &SettingsStore,
+) -> String {
+	let Some(toggle) = string_option(options, "setting").and_then(Toggle::from_name) else {
+		return String::from("Which setting?");
+	};
+	let Some(on) = bool_option(options, "enabled") else {
+		return String::from("On or off?");
+	};
+	let state = if on { "on" } else { "off" };
+	saved(
+		settings.set_toggle(guild, toggle, on).await,
+		format!("{} is now {state}.", toggle.description()),
+	)
+}
+
+/// The confirmation if the setting was saved, or an apology if it was not.
+fn saved(result: rusqlite::Result<()>, confirmation: String) -> String {
+	match result {
+		Ok(()) => confirmation,
+		Err(error) => {
+			eprintln!("Could not save a setting: {error}");
+			String::from("Something went wrong saving that setting.")
+		}
+	}
+}
+
+/// The options of the subcommand within the group.
+fn subcommand_options<'a>(options: &'a [ResolvedOption<'a>]) -> Option<&'a [ResolvedOption<'a>]> {
+	let ResolvedValue::SubCommandGroup(options) = &options.first()?.value else {
+		return None;
+	};
+	let ResolvedValue::SubCommand(options) = &options.first()?.value else {
+		return None;
+	};
+	Some(options)
+}
+
+fn string_option<'a>(options: &[ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+	options.iter().find_map(|option| match option.value {
+		ResolvedValue::String(value) if option.name == name => Some(value),
+		_ => None,
+	})
+}
+
+fn bool_option(options: &[ResolvedOption<'_>], name: &str) -> Option<bool> {
+	options.iter().find_map(|option| match option.value {
+		ResolvedValue::Boolean(value) if option.name == name => Some(value),
+		_ => None,
+	})
+}
+
+fn rule_option() -> CreateCommandOption {
+	CreateCommandOption::new(CommandOptionType::String, "rule", "The name of the rule.")
+		.required(true)
+		.set_autocomplete(true)
+}
+
+fn subcommand(name: &str, description: &str) -> CreateCommandOption {
+	CreateCommandOption::new(CommandOptionType::SubCommand, name, description)
+}
+
+pub fn create_command() -> CreateCommand {
+	let toggle = Toggle::ALL.into_iter().fold(
+		CreateCommandOption::new(
+			CommandOptionType::String,
+			"setting",
+			"What to turn on or off.",
+		)
+		.required(true),
+		|option, toggle| option.add_string_choice(toggle.description(), toggle.name()),
+	);
+	let settings = CreateCommandOption::new(
+		CommandOptionType::SubCommandGroup,
+		"settings",
+		"Change how I behave in this server. Needs Manage Server.",
+	)
+	.add_sub_option(subcommand("show", "Show the settings of this server."))
+	.add_sub_option(subcommand("enable", "Turn a rule on.").add_sub_option(rule_option()))
+	.add_sub_option(subcommand("disable", "Turn a rule off.").add_sub_option(rule_option()))
+	.add_sub_option(
+		subcommand("mirror", "Choose which mirror a rule uses first.")
+			.add_sub_option(rule_option())
+			.add_sub_option(
+				CreateCommandOption::new(
+					CommandOptionType::String,
+					"host",
+					"The mirror to use while it is up. Leave out to go back to the usual order.",
+				)
+				.set_autocomplete(true),
+			),
+	)
+	.add_sub_option(
+		subcommand(
+			"channel",
+			"Choose whether links in a channel are fixed automatically.",
+		)
+		.add_sub_option(
+			CreateCommandOption::new(
+				CommandOptionType::Channel,
+				"channel",
+				"The channel, category or thread. Channels follow their category, and threads their channel.",
+			)
+			.required(true),
+		)
+		.add_sub_option(
+			CreateCommandOption::new(
+				CommandOptionType::String,
+				"mode",
+				"Whether to fix links there.",
+			)
+			.required(true)
+			.add_string_choice("Always", ChannelRule::Allow.name())
+			.add_string_choice("Never", ChannelRule::Deny.name())
+			.add_string_choice("Like what it is in", "inherit"),
+		),
+	)
+	.add_sub_option(
+		subcommand(
+			"toggle",
+			"Turn automatic fixing, reactions or embed suppression on or off.",
+		)
+		.add_sub_option(toggle)
+		.add_sub_option(
+			CreateCommandOption::new(CommandOptionType::Boolean, "enabled", "On or off.")
+				.required(true),
+		),
+	);
+	let everywhere = || {
+		CreateCommandOption::new(
+			CommandOptionType::Boolean,
+			"everywhere",
+			"Whether this goes for every server, rather than only this one.",
+		)
+	};
+	CreateCommand::new("linkfix")
+		.description("Settings for how I fix links.")
+		.add_option(settings)
+		.add_option(
+			subcommand(
+				"optout",
+				"Stop me from replying to your links on my own. You can still ask me to fix them.",
+			)
+			.add_sub_option(everywhere()),
+		)
+		.add_option(
+			subcommand("optin", "Let me reply to your links on my own again.")
+				.add_sub_option(everywhere()),
+		)
+		.contexts(vec![InteractionContext::Guild, InteractionContext::BotDm])
+}
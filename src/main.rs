@@ -1,33 +1,107 @@
-use std::fs;
+use std::{fs, sync::Arc};
 
 use discord_event_handler::DiscordEventHandler;
 use fix_existing_message::{FutureEmbedRemovals, FutureEmbedRemovalsTypeMap};
-use serenity::all::GatewayIntents;
+use serenity::{all::GatewayIntents, cache::Settings as CacheSettings};
 
-use crate::fix_link::LinkFixer;
+use crate::{
+	database::{DATABASE_FILE, Database},
+	link_fixer_handle::{
+		LEGACY_RULE_FILE, LinkFixerHandle, load_link_fixer, print_config_errors, print_load_error,
+		read_rule_file, watch_rule_files,
+	},
+	replies::ReplyStore,
+	resolver::{CachedResolver, HttpResolver},
+	settings::SettingsStore,
+};
 
 mod automatic;
+mod check;
+mod clearurls;
+mod config_error;
 mod context_menu;
+mod database;
+mod delete_button;
 mod discord_event_handler;
+mod filter;
 mod fix_existing_message;
 mod fix_link;
+mod link_fixer_handle;
+mod linkfix_command;
+mod markdown;
+mod mirrors;
+mod owner_command;
+mod replies;
 mod reply_shortcuts;
+mod resolver;
+mod rule_file;
+mod settings;
 mod slash_command;
 mod strings;
+#[cfg(test)]
+mod stub_server;
+mod tokenizer;
+mod tracking;
 mod util;
 
+/// How many of the latest messages in each channel to keep, so edits to them can be compared with what they were before.
+const CACHED_MESSAGES_PER_CHANNEL: usize = 100;
+
 #[tokio::main]
 async fn main() {
-	let config = std::fs::read_to_string("./replacements.txt").unwrap();
-	let link_fixer = LinkFixer::from_config(&config);
+	let mut args = std::env::args().skip(1);
+	match args.next().as_deref() {
+		Some("convert") => {
+			convert_rule_file(args.next(), args.next());
+			return;
+		}
+		Some("check") => check_rule_file(args.next()),
+		_ => (),
+	}
 
+	let link_fixer = match load_link_fixer() {
+		Ok(link_fixer) => Arc::new(LinkFixerHandle::new(link_fixer)),
+		Err(error) => {
+			print_load_error(&error);
+			std::process::exit(1);
+		}
+	};
+	tokio::spawn(watch_rule_files(link_fixer.clone()));
+	tokio::spawn(mirrors::watch_mirrors(link_fixer.clone()));
+	let removals = Arc::new(FutureEmbedRemovals::new());
+	tokio::spawn(fix_existing_message::sweep_embed_removals(
+		link_fixer.clone(),
+		removals.clone(),
+	));
+
+	let database = Database::open(DATABASE_FILE).expect("Could not open the database");
+	let settings = Arc::new(
+		SettingsStore::new(database.clone())
+			.await
+			.expect("Could not set up the settings in the database"),
+	);
+	let replies = Arc::new(
+		ReplyStore::new(database)
+			.await
+			.expect("Could not set up the replies in the database"),
+	);
+	tokio::spawn(replies::forget_old_replies(replies.clone()));
 	let discord_token = fs::read_to_string("./token.txt").expect("Could not read token file");
+	// Edits are compared against the message from before, which is only known if it is cached.
+	let mut cache_settings = CacheSettings::default();
+	cache_settings.max_messages = CACHED_MESSAGES_PER_CHANNEL;
 
 	let mut client = serenity::Client::builder(
 		&discord_token,
 		GatewayIntents::GUILDS | GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT,
 	)
-	.event_handler(DiscordEventHandler::new(link_fixer))
+	.cache_settings(cache_settings)
+	.event_handler(DiscordEventHandler::new(
+		link_fixer,
+		Arc::new(CachedResolver::new(HttpResolver::new())),
+		settings,
+		replies,
+	))
 	.await
 	.expect("Error creating Discord client");
 
@@ -35,9 +109,44 @@ async fn main() {
 		.data
 		.write()
 		.await
-		.insert::<FutureEmbedRemovalsTypeMap>(FutureEmbedRemovals::new());
+		.insert::<FutureEmbedRemovalsTypeMap>(removals);
 
 	if let Err(why) = client.start().await {
 		eprintln!("Error with client: {:?}", why);
 	}
 }
+
+/// Converts a rule file in the older line-based format to the structured format, writing it to the output path if given, or printing it otherwise.
+fn convert_rule_file(input: Option<String>, output: Option<String>) {
+	let input = input.as_deref().unwrap_or(LEGACY_RULE_FILE);
+	let config = fs::read_to_string(input).expect("Could not read rule file to convert");
+	let converted = match rule_file::convert_legacy(&config) {
+		Ok(converted) => converted,
+		Err(error) => {
+			print_config_errors(&[error]);
+			std::process::exit(1);
+		}
+	};
+	match output {
+		Some(output) => {
+			fs::write(&output, converted).expect("Could not write converted rule file");
+			println!("Wrote the converted rules to {output}.");
+		}
+		None => print!("{converted}"),
+	}
+}
+
+/// Checks the rules in the given file, or the one the bot would use, and exits.
+fn check_rule_file(path: Option<String>) -> ! {
+	let config = match path {
+		Some(path) => fs::read_to_string(&path).unwrap_or_else(|_| {
+			eprintln!("Could not read {path}.");
+			std::process::exit(1);
+		}),
+		None => read_rule_file().unwrap_or_else(|error| {
+			print_load_error(&error);
+			std::process::exit(1);
+		}),
+	};
+	std::process::exit(if check::check_rules(&config) { 0 } else { 1 });
+}
@@ -0,0 +1,138 @@
+use std::ops::Range;
+
+/// Finds the parts of a message where links are not meant to be fixed: inline code, code blocks and, unless `include_quotes`, block quotes. Returns their spans in bytes.
+pub fn excluded_ranges(text: &str, include_quotes: bool) -> Vec<Range<usize>> {
+	let mut ranges = code_ranges(text);
+	if !include_quotes {
+		ranges.extend(quote_ranges(text));
+	}
+	ranges
+}
+
+/// Finds the parts of the text between spoiler tags (`||`), including the tags. Tags in code do not count.
+pub fn spoiler_ranges(text: &str) -> Vec<Range<usize>> {
+	let code = code_ranges(text);
+	let mut tags = Vec::new();
+	let mut index = 0;
+	while let Some(found) = text[index..].find("||") {
+		let start = index + found;
+		if !code.iter().any(|range| range.contains(&start)) {
+			tags.push(start);
+		}
+		index = start + 2;
+	}
+	tags.chunks_exact(2)
+		.map(|pair| pair[0]..pair[1] + 2)
+		.collect()
+}
+
+/// Inline code (with one or two backticks) and code blocks (with three). A backtick escaped with `\` does not start any.
+fn code_ranges(text: &str) -> Vec<Range<usize>> {
+	let bytes = text.as_bytes();
+	let mut ranges = Vec::new();
+	let mut index = 0;
+	while index < bytes.len() {
+		match bytes[index] {
+			b'\\' => index += 2,
+			b'`' => {
+				let run = bytes[index..]
+					.iter()
+					.take_while(|&&byte| byte == b'`')
+					.count();
+				let end = if run >= 3 {
+					// Like Discord, more than three backticks open a code block with the rest as content.
+					text[index + 3..]
+						.find("```")
+						.filter(|&content_len| content_len > 0)
+						.map(|content_len| index + 3 + content_len + 3)
+				} else {
+					closing_run(bytes, index + run, run).map(|closing| closing + run)
+				};
+				match end {
+					Some(end) => {
+						ranges.push(index..end);
+						index = end;
+					}
+					None => index += run,
+				}
+			}
+			_ => index += 1,
+		}
+	}
+	ranges
+}
+
+/// Where the next run of exactly `len` backticks starts, not counting an immediate one.
+fn closing_run(bytes: &[u8], from: usize, len: usize) -> Option<usize> {
+	let mut index = from + 1;
+	while index < bytes.len() {
+		if bytes[index] == b'`' {
+			let run = bytes[index..]
+				.iter()
+				.take_while(|&&byte| byte == b'`')
+				.count();
+			if run == len {
+				return Some(index);
+			}
+			index += run;
+		} else {
+			index += 1;
+		}
+	}
+	None
+}
+
+/// Lines starting with `> `, and everything after a line starting with `>>> `.
+fn quote_ranges(text: &str) -> Vec<Range<usize>> {
+	let mut ranges = Vec::new();
+	let mut offset = 0;
+	for line in text.split_inclusive('\n') {
+		let quote = line.trim_start_matches([' ', '\t']);
+		if quote.starts_with(">>> ") {
+			ranges.push(offset..text.len());
+			break;
+		}
+		if quote.starts_with("> ") {
+			ranges.push(offset..offset + line.len());
+		}
+		offset += line.len();
+	}
+	ranges
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn excluded(text: &str, include_quotes: bool) -> Vec<&str> {
+		excluded_ranges(text, include_quotes)
+			.into_iter()
+			.map(|range| &text[range])
+			.collect()
+	}
+
+	#[test]
+	fn code() {
+		assert_eq!(
+			excluded("a `b` ``c ` d`` \\`e ```rust\nf\n``` `g` `h", false),
+			["`b`", "``c ` d``", "```rust\nf\n```", "`g`"]
+		);
+	}
+	#[test]
+	fn quotes() {
+		let text = "a\n> b\nc\n>>> d\ne";
+		assert_eq!(excluded(text, false), ["> b\n", ">>> d\ne"]);
+		assert_eq!(excluded(text, true), Vec::<&str>::new());
+	}
+	#[test]
+	fn spoilers() {
+		let text = "||a|| `||` b ||c|| ||d";
+		assert_eq!(
+			spoiler_ranges(text)
+				.into_iter()
+				.map(|range| &text[range])
+				.collect::<Vec<_>>(),
+			["||a||", "||c||"]
+		);
+	}
+}
@@ -0,0 +1,258 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	fs,
+	sync::{
+		Arc, RwLock,
+		atomic::{AtomicBool, Ordering},
+	},
+	time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{link_fixer_handle::LinkFixerHandle, util::unix_time};
+
+/// Where the health of the mirrors is kept between runs.
+pub const MIRROR_STATUS_FILE: &str = "./mirror_status.json";
+/// How long a check saying a mirror is down counts for, before trying it again.
+const DOWN_DURATION: Duration = Duration::from_secs(30 * 60);
+/// How often to check the canaries of the configured mirrors.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How long a canary gets to answer.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many checks to remember per mirror.
+const HISTORY_LENGTH: usize = 20;
+/// How many replies in a row must miss their embeds on a mirror for it to count as down without its canary failing. A single one can miss it because of the post, like a deleted or private one.
+const EMBED_FAILURES_TO_DOWN: usize = 3;
+
+/// How the health of a mirror was found out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckSource {
+	/// A request to its canary link.
+	Canary,
+	/// Whether a reply of the bot using it got its embeds.
+	Embed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Check {
+	/// In seconds since the Unix epoch, so it can be saved.
+	at: u64,
+	up: bool,
+	source: CheckSource,
+}
+
+impl Check {
+	fn age(&self) -> Duration {
+		Duration::from_secs(unix_time().saturating_sub(self.at))
+	}
+	/// Whether the check said the mirror was down, recently enough to still count.
+	fn is_recent_failure(&self) -> bool {
+		!self.up && self.age() < DOWN_DURATION
+	}
+}
+
+/// Whether the recent failures at the end of the history say the mirror is down: a failed canary, or enough replies in a row without embeds.
+fn is_down(history: &VecDeque<Check>) -> bool {
+	let mut failures = 0;
+	for check in history
+		.iter()
+		.rev()
+		.take_while(|check| check.is_recent_failure())
+	{
+		if check.source == CheckSource::Canary {
+			return true;
+		}
+		failures += 1;
+	}
+	failures >= EMBED_FAILURES_TO_DOWN
+}
+
+/// The health of the sites that links are replaced with (mirrors), by host. Shared by every version of the rules, so it survives reloads, and saved to disk every so often, so it survives restarts.
+#[derive(Debug, Default)]
+pub struct MirrorStatus {
+	checks: RwLock<HashMap<String, VecDeque<Check>>>,
+	/// Where to save the checks, if anywhere.
+	path: Option<String>,
+	/// Whether there are checks that were not saved yet.
+	unsaved: AtomicBool,
+}
+
+impl MirrorStatus {
+	/// Keeps the checks in memory only.
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// Loads the checks from the file, if it exists, and saves them there from then on.
+	pub fn load(path: &str) -> Self {
+		let checks = match fs::read_to_string(path) {
+			Ok(json) => serde_json::from_str(&json).unwrap_or_else(|error| {
+				eprintln!("Could not read the mirror status in {path}, so I started over: {error}");
+				HashMap::new()
+			}),
+			Err(_) => HashMap::new(),
+		};
+		Self {
+			checks: RwLock::new(checks),
+			path: Some(path.to_string()),
+			unsaved: AtomicBool::new(false),
+		}
+	}
+	/// Saves the checks to the file, if there are new ones. Blocks on the file system, so call it from `spawn_blocking` in async code.
+	pub fn save(&self) {
+		let Some(path) = &self.path else {
+			return;
+		};
+		if !self.unsaved.swap(false, Ordering::Relaxed) {
+			return;
+		}
+		// The lock is released before writing.
+		let json = serde_json::to_string(&*self.checks.read().unwrap()).unwrap();
+		if let Err(error) = fs::write(path, json) {
+			eprintln!("Could not save the mirror status to {path}: {error}");
+		}
+	}
+	pub fn record(&self, host: &str, up: bool, source: CheckSource) {
+		let mut checks = self.checks.write().unwrap();
+		let history = checks.entry(host.to_ascii_lowercase()).or_default();
+		if !up && history.back().is_none_or(|check| check.up) {
+			println!("Mirror {host} seems to be down.");
+		}
+		history.push_back(Check {
+			at: unix_time(),
+			up,
+			source,
+		});
+		if history.len() > HISTORY_LENGTH {
+			history.pop_front();
+		}
+		self.unsaved.store(true, Ordering::Relaxed);
+	}
+	/// Whether the last checks of the mirror said it was down, recently enough to still count.
+	pub fn is_down(&self, host: &str) -> bool {
+		self.checks
+			.read()
+			.unwrap()
+			.get(&host.to_ascii_lowercase())
+			.is_some_and(is_down)
+	}
+	/// A line per mirror, with its status, when that was found out and how, and its recent history, oldest first. Also lists the given hosts if they were never checked.
+	pub fn table(&self, hosts: &[String]) -> String {
+		let checks = self.checks.read().unwrap();
+		let mut lines = Vec::new();
+		let mut all_hosts = checks.keys().cloned().collect::<Vec<_>>();
+		all_hosts.extend(hosts.iter().map(|host| host.to_ascii_lowercase()));
+		all_hosts.sort();
+		all_hosts.dedup();
+		for host in all_hosts {
+			let Some(last) = checks.get(&host).and_then(VecDeque::back) else {
+				lines.push(format!("{host}: never checked"));
+				continue;
+			};
+			let status = if last.up {
+				"up"
+			} else if is_down(&checks[&host]) {
+				"down"
+			} else if last.is_recent_failure() {
+				"failing"
+			} else {
+				"down a while ago"
+			};
+			let history = checks[&host]
+				.iter()
+				.map(|check| if check.up { '✅' } else { '❌' })
+				.collect::<String>();
+			let source = match last.source {
+				CheckSource::Canary => "canary",
+				CheckSource::Embed => "embed",
+			};
+			lines.push(format!(
+				"{host}: {status} ({source}, {} min ago) {history}",
+				last.age().as_secs() / 60
+			));
+		}
+		lines.join("\n")
+	}
+}
+
+/// The host of a link, like `fixupx.com` for `https://fixupx.com/a/status/1`.
+pub fn host_of(link: &str) -> Option<&str> {
+	let (_, rest) = link.split_once("://")?;
+	let host = rest.split(['/', '?', '#']).next()?;
+	(!host.is_empty()).then_some(host)
+}
+
+/// Checks the canaries of the mirrors in the current rules every so often, and saves those and any other checks since the last time.
+pub async fn watch_mirrors(handle: Arc<LinkFixerHandle>) {
+	let client = reqwest::Client::builder()
+		.timeout(CHECK_TIMEOUT)
+		.build()
+		.expect("Could not build the HTTP client");
+	loop {
+		let link_fixer = handle.get().await;
+		for mirror in link_fixer.mirror_definitions() {
+			let up = check_canary(&client, &mirror.canary).await;
+			handle
+				.mirrors()
+				.record(&mirror.host, up, CheckSource::Canary);
+		}
+		let saving_handle = handle.clone();
+		let _ = tokio::task::spawn_blocking(move || saving_handle.mirrors().save()).await;
+		tokio::time::sleep(CHECK_INTERVAL).await;
+	}
+}
+
+/// Whether the canary answers a HEAD request with success or a redirect.
+async fn check_canary(client: &reqwest::Client, canary: &str) -> bool {
+	client
+		.head(canary)
+		.send()
+		.await
+		.is_ok_and(|response| response.status().is_success() || response.status().is_redirection())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::stub_server;
+
+	#[tokio::test]
+	async fn canary_checks() {
+		let address = stub_server::start(|method, path| match (method, path) {
+			("HEAD", "/up") => stub_server::status(200),
+			_ => stub_server::status(503),
+		})
+		.await;
+		let client = reqwest::Client::new();
+		assert!(check_canary(&client, &format!("http://{address}/up")).await);
+		assert!(!check_canary(&client, &format!("http://{address}/down")).await);
+	}
+	#[test]
+	fn persisted_status() {
+		let path = std::env::temp_dir().join("linkfixbot-mirror-status-test.json");
+		let path = path.to_str().unwrap();
+		let _ = fs::remove_file(path);
+
+		let status = MirrorStatus::load(path);
+		status.record("fixupx.com", true, CheckSource::Canary);
+		status.record("FIXUPX.com", false, CheckSource::Embed);
+		// A single reply without embeds could be because of the post.
+		assert!(!status.is_down("fixupx.com"));
+		status.record("fixupx.com", false, CheckSource::Embed);
+		status.record("fixupx.com", false, CheckSource::Embed);
+		status.record("vxtwitter.com", true, CheckSource::Canary);
+		status.record("vxtiktok.com", false, CheckSource::Canary);
+		assert!(status.is_down("fixupx.com"));
+		assert!(!status.is_down("vxtwitter.com"));
+		assert!(status.is_down("vxtiktok.com"));
+		status.save();
+
+		let status = MirrorStatus::load(path);
+		assert!(status.is_down("fixupx.com"));
+		assert_eq!(
+			status.table(&[String::from("ddinstagram.com")]),
+			"ddinstagram.com: never checked\nfixupx.com: down (embed, 0 min ago) ✅❌❌❌\nvxtiktok.com: down (canary, 0 min ago) ❌\nvxtwitter.com: up (canary, 0 min ago) ✅"
+		);
+	}
+}
@@ -0,0 +1,113 @@
+use serenity::all::*;
+
+use crate::{
+	fix_existing_message::FutureEmbedRemovalsTypeMap,
+	link_fixer_handle::{LEGACY_RULE_FILE, LinkFixerHandle, LoadError, RULE_FILE},
+	reply_shortcuts::ReplyShortcuts,
+	util::truncate_message,
+};
+
+/// Whether the user owns the bot, either directly or through its team.
+async fn is_owner(context: &Context, user: UserId) -> bool {
+	let Ok(info) = context.http.get_current_application_info().await else {
+		eprintln!("Could not get the application info to check for ownership.");
+		return false;
+	};
+	info.owner.is_some_and(|owner| owner.id == user)
+		|| info
+			.team
+			.is_some_and(|team| team.members.iter().any(|member| member.user.id == user))
+}
+
+pub async fn handle(
+	context: &Context,
+	interaction: CommandInteraction,
+	link_fixer: &LinkFixerHandle,
+) {
+	if !is_owner(context, interaction.user.id).await {
+		let _ = interaction
+			.ephemeral_reply(&context.http, "Only the owner of the bot can do this.")
+			.await;
+		return;
+	}
+	let Some(subcommand) = interaction.data.options.first() else {
+		return;
+	};
+	match subcommand.name.as_str() {
+		"reload" => reload(context, &interaction, link_fixer).await,
+		"health" => health(context, &interaction, link_fixer).await,
+		_ => (),
+	}
+}
+
+async fn reload(context: &Context, interaction: &CommandInteraction, link_fixer: &LinkFixerHandle) {
+	let response = match link_fixer.reload().await {
+		Ok(()) => String::from("Reloaded the rules."),
+		Err(LoadError::Unreadable) => {
+			format!("Could not read {RULE_FILE} or {LEGACY_RULE_FILE}, so I kept the old rules.")
+		}
+		Err(LoadError::Config(errors)) => {
+			let mut response = format!(
+				"Found {} problem(s) with the rules, so I kept the old ones:",
+				errors.len()
+			);
+			for error in errors {
+				response.push_str(&format!("\n- {error}"));
+			}
+			response
+		}
+	};
+	let _ = interaction
+		.ephemeral_reply(&context.http, truncate_message(response))
+		.await;
+}
+
+/// Shows how the mirrors the links get replaced with are doing, and how the pairing of embeds is going.
+async fn health(context: &Context, interaction: &CommandInteraction, link_fixer: &LinkFixerHandle) {
+	let hosts = link_fixer
+		.get()
+		.await
+		.mirror_definitions()
+		.iter()
+		.map(|mirror| mirror.host.clone())
+		.collect::<Vec<_>>();
+	let table = link_fixer.mirrors().table(&hosts);
+	let mut response = if table.is_empty() {
+		String::from("No mirrors have been checked yet, and none are configured.")
+	} else {
+		table
+	};
+	let removals = context
+		.data
+		.read()
+		.await
+		.get::<FutureEmbedRemovalsTypeMap>()
+		.cloned();
+	if let Some(removals) = removals {
+		let counts = removals.counts().await;
+		response.push_str(&format!(
+			"\n\nEmbed pairings: {} succeeded, {} timed out, {} waiting",
+			counts.succeeded, counts.timed_out, counts.waiting
+		));
+	}
+	let _ = interaction
+		.ephemeral_reply(&context.http, truncate_message(response))
+		.await;
+}
+
+pub fn create_command() -> CreateCommand {
+	CreateCommand::new("owner")
+		.description("Commands for the owner of the bot.")
+		.add_option(CreateCommandOption::new(
+			CommandOptionType::SubCommand,
+			"reload",
+			"Reload the replacement rules from disk.",
+		))
+		.add_option(CreateCommandOption::new(
+			CommandOptionType::SubCommand,
+			"health",
+			"Show which mirrors seem to be up, and how embed replacement is going.",
+		))
+		.default_member_permissions(Permissions::ADMINISTRATOR)
+		.contexts(vec![InteractionContext::Guild, InteractionContext::BotDm])
+}
@@ -0,0 +1,387 @@
+use std::{sync::Arc, time::Duration};
+
+use itertools::Itertools;
+use rusqlite::{OptionalExtension, Row, params};
+use serenity::all::{ChannelId, MessageId, UserId};
+
+use crate::{database::Database, util::unix_time};
+
+/// How long to remember replies for. Messages older than this are rarely deleted or edited.
+const REPLY_MEMORY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How often to forget the replies older than that.
+const FORGET_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Whether the message is recent enough that a reply to it would still be remembered.
+pub fn is_remembered(message: MessageId) -> bool {
+	let created_at = message.created_at().unix_timestamp().max(0) as u64;
+	unix_time().saturating_sub(created_at) < REPLY_MEMORY.as_secs()
+}
+
+/// A reply the bot made on its own to a message with links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+	/// The channel both messages are in.
+	pub channel: ChannelId,
+	pub reply: MessageId,
+	/// The author of the original message.
+	pub author: UserId,
+}
+
+/// What is remembered about a message the bot fixed the links of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+	/// `None` if there is no reply to keep up to date, because it was dismissed or the links were fixed through the context menu instead.
+	pub reply: Option<Reply>,
+	/// The fixable links in the message when it was last fixed, sorted.
+	pub links: Vec<String>,
+}
+
+/// The embeds of an original message that the bot suppressed, because its reply had them instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suppression {
+	/// The channel both messages are in.
+	pub channel: ChannelId,
+	pub original: MessageId,
+	/// How many embeds the reply had at the time.
+	pub embed_count: usize,
+}
+
+/// Which messages the bot replied to, and with what, and which of those it suppressed the embeds of, in the database so it survives restarts.
+pub struct ReplyStore {
+	database: Database,
+}
+
+impl ReplyStore {
+	/// Creates the tables for the replies in the database if needed.
+	pub async fn new(database: Database) -> rusqlite::Result<Self> {
+		database
+			.run(|connection| {
+				connection.execute_batch(
+					"CREATE TABLE IF NOT EXISTS replies (
+						original INTEGER PRIMARY KEY,
+						channel INTEGER NOT NULL,
+						reply INTEGER,
+						author INTEGER NOT NULL,
+						links TEXT NOT NULL,
+						created_at INTEGER NOT NULL
+					);
+					CREATE INDEX IF NOT EXISTS replies_created_at ON replies (created_at);
+					CREATE TABLE IF NOT EXISTS suppressions (
+						reply INTEGER PRIMARY KEY,
+						channel INTEGER NOT NULL,
+						original INTEGER NOT NULL,
+						embed_count INTEGER NOT NULL,
+						created_at INTEGER NOT NULL
+					);
+					CREATE INDEX IF NOT EXISTS suppressions_created_at ON suppressions (created_at);",
+				)
+			})
+			.await?;
+		Ok(Self { database })
+	}
+	/// Remembers the reply to the original message and the fixable links it was made for.
+	pub async fn record(&self, original: MessageId, reply: Reply, links: &[&str]) {
+		self.insert(
+			"INSERT OR REPLACE INTO replies (original, channel, reply, author, links, created_at)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+			original,
+			reply.channel,
+			Some(reply.reply),
+			reply.author,
+			links,
+		)
+		.await;
+	}
+	/// Remembers not to reply to the original message on its own, because its links were already fixed another way. Keeps an existing reply.
+	pub async fn leave_alone(
+		&self,
+		original: MessageId,
+		channel: ChannelId,
+		author: UserId,
+		links: &[&str],
+	) {
+		self.insert(
+			"INSERT INTO replies (original, channel, reply, author, links, created_at)
+			VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+			ON CONFLICT (original) DO NOTHING",
+			original,
+			channel,
+			None,
+			author,
+			links,
+		)
+		.await;
+	}
+	async fn insert(
+		&self,
+		query: &'static str,
+		original: MessageId,
+		channel: ChannelId,
+		reply: Option<MessageId>,
+		author: UserId,
+		links: &[&str],
+	) {
+		let links = links.iter().sorted().dedup().join("\n");
+		let result = self
+			.database
+			.run(move |connection| {
+				connection.execute(
+					query,
+					params![
+						original.get(),
+						channel.get(),
+						reply.map(MessageId::get),
+						author.get(),
+						links,
+						unix_time()
+					],
+				)
+			})
+			.await;
+		if let Err(error) = result {
+			eprintln!(
+				"Could not remember the reply to {}: {error}",
+				original.get()
+			);
+		}
+	}
+	/// Remembers that the reply's embeds took the place of the original's.
+	pub async fn record_suppression(&self, reply: MessageId, suppression: Suppression) {
+		let result = self
+			.database
+			.run(move |connection| {
+				connection.execute(
+					"INSERT OR REPLACE INTO suppressions (reply, channel, original, embed_count, created_at)
+					VALUES (?1, ?2, ?3, ?4, ?5)",
+					params![
+						reply.get(),
+						suppression.channel.get(),
+						suppression.original.get(),
+						suppression.embed_count,
+						unix_time()
+					],
+				)
+			})
+			.await;
+		if let Err(error) = result {
+			eprintln!(
+				"Could not remember suppressing the embeds of {}: {error}",
+				suppression.original.get()
+			);
+		}
+	}
+	/// The suppression the reply's embeds were the reason for, if any.
+	pub async fn suppression(&self, reply: MessageId) -> Option<Suppression> {
+		self.query_suppression(
+			"SELECT channel, original, embed_count FROM suppressions WHERE reply = ?1",
+			reply,
+		)
+		.await
+	}
+	/// Forgets the suppression the reply's embeds were the reason for, returning it if there was one.
+	pub async fn take_suppression(&self, reply: MessageId) -> Option<Suppression> {
+		self.query_suppression(
+			"DELETE FROM suppressions WHERE reply = ?1 RETURNING channel, original, embed_count",
+			reply,
+		)
+		.await
+	}
+	async fn query_suppression(
+		&self,
+		query: &'static str,
+		reply: MessageId,
+	) -> Option<Suppression> {
+		self.database
+			.run(move |connection| {
+				connection
+					.query_row(query, params![reply.get()], |row| {
+						Ok(Suppression {
+							channel: ChannelId::new(row.get(0)?),
+							original: MessageId::new(row.get(1)?),
+							embed_count: row.get(2)?,
+						})
+					})
+					.optional()
+			})
+			.await
+			.unwrap_or_else(|error| {
+				eprintln!(
+					"Could not look up what the embeds of {} replaced: {error}",
+					reply.get()
+				);
+				None
+			})
+	}
+	/// Forgets the reply to the original message, but remembers not to reply to it again, like after the reply was dismissed.
+	pub async fn dismiss(&self, original: MessageId) {
+		let result = self
+			.database
+			.run(move |connection| {
+				connection.execute(
+					"UPDATE replies SET reply = NULL WHERE original = ?1",
+					params![original.get()],
+				)
+			})
+			.await;
+		if let Err(error) = result {
+			eprintln!(
+				"Could not remember dismissing the reply to {}: {error}",
+				original.get()
+			);
+		}
+	}
+	/// The reply to the original message, if there is one to keep up to date.
+	pub async fn get(&self, original: MessageId) -> Option<Reply> {
+		self.lookup(original).await?.reply
+	}
+	/// What is remembered about the original message, if anything.
+	pub async fn lookup(&self, original: MessageId) -> Option<Record> {
+		self.query_record(
+			"SELECT channel, reply, author, links FROM replies WHERE original = ?1",
+			original,
+		)
+		.await
+	}
+	/// Forgets the original message, returning its reply if there was one to keep up to date.
+	pub async fn take(&self, original: MessageId) -> Option<Reply> {
+		self.query_record(
+			"DELETE FROM replies WHERE original = ?1 RETURNING channel, reply, author, links",
+			original,
+		)
+		.await?
+		.reply
+	}
+	async fn query_record(&self, query: &'static str, original: MessageId) -> Option<Record> {
+		self.database
+			.run(move |connection| {
+				connection
+					.query_row(query, params![original.get()], read_record)
+					.optional()
+			})
+			.await
+			.unwrap_or_else(|error| {
+				eprintln!("Could not look up the reply to {}: {error}", original.get());
+				None
+			})
+	}
+	/// Forgets the replies and suppressions older than `REPLY_MEMORY`.
+	pub async fn forget_old(&self) -> rusqlite::Result<()> {
+		let cutoff = unix_time().saturating_sub(REPLY_MEMORY.as_secs());
+		self.database
+			.run(move |connection| {
+				connection.execute("DELETE FROM replies WHERE created_at < ?1", params![cutoff])?;
+				connection.execute(
+					"DELETE FROM suppressions WHERE created_at < ?1",
+					params![cutoff],
+				)?;
+				Ok(())
+			})
+			.await
+	}
+}
+
+fn read_record(row: &Row) -> rusqlite::Result<Record> {
+	let channel = ChannelId::new(row.get(0)?);
+	let author = UserId::new(row.get(2)?);
+	let links: String = row.get(3)?;
+	Ok(Record {
+		reply: row.get::<_, Option<u64>>(1)?.map(|reply| Reply {
+			channel,
+			reply: MessageId::new(reply),
+			author,
+		}),
+		links: links
+			.split('\n')
+			.filter(|link| !link.is_empty())
+			.map(str::to_string)
+			.collect(),
+	})
+}
+
+/// Forgets old replies every so often.
+pub async fn forget_old_replies(replies: Arc<ReplyStore>) {
+	loop {
+		if let Err(error) = replies.forget_old().await {
+			eprintln!("Could not forget old replies: {error}");
+		}
+		tokio::time::sleep(FORGET_INTERVAL).await;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn remember_replies() {
+		let store = ReplyStore::new(Database::open(":memory:").unwrap())
+			.await
+			.unwrap();
+		let reply = Reply {
+			channel: ChannelId::new(1),
+			reply: MessageId::new(3),
+			author: UserId::new(4),
+		};
+		store
+			.record(
+				MessageId::new(2),
+				reply,
+				&["https://b.com/", "https://a.com/"],
+			)
+			.await;
+		assert_eq!(store.take(MessageId::new(5)).await, None);
+		assert_eq!(store.get(MessageId::new(2)).await, Some(reply));
+		// A reply already being there wins over fixing the links another way.
+		store
+			.leave_alone(MessageId::new(2), reply.channel, reply.author, &[])
+			.await;
+		assert_eq!(
+			store.lookup(MessageId::new(2)).await,
+			Some(Record {
+				reply: Some(reply),
+				links: vec![
+					String::from("https://a.com/"),
+					String::from("https://b.com/")
+				],
+			})
+		);
+		assert_eq!(store.take(MessageId::new(2)).await, Some(reply));
+		assert_eq!(store.take(MessageId::new(2)).await, None);
+
+		store.record(MessageId::new(7), reply, &[]).await;
+		store.dismiss(MessageId::new(7)).await;
+		assert_eq!(store.get(MessageId::new(7)).await, None);
+		assert!(store.lookup(MessageId::new(7)).await.is_some());
+
+		store
+			.leave_alone(MessageId::new(6), reply.channel, reply.author, &[])
+			.await;
+		assert_eq!(store.get(MessageId::new(6)).await, None);
+		assert_eq!(
+			store.lookup(MessageId::new(6)).await,
+			Some(Record {
+				reply: None,
+				links: Vec::new(),
+			})
+		);
+
+		let suppression = Suppression {
+			channel: ChannelId::new(1),
+			original: MessageId::new(2),
+			embed_count: 2,
+		};
+		store
+			.record_suppression(MessageId::new(3), suppression)
+			.await;
+		assert_eq!(store.suppression(MessageId::new(2)).await, None);
+		assert_eq!(
+			store.suppression(MessageId::new(3)).await,
+			Some(suppression)
+		);
+		assert_eq!(
+			store.take_suppression(MessageId::new(3)).await,
+			Some(suppression)
+		);
+		assert_eq!(store.suppression(MessageId::new(3)).await, None);
+	}
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use serenity::{
 	Result as SerenityResult,
-	all::CommandInteraction,
+	all::{CommandInteraction, ComponentInteraction},
 	async_trait,
 	builder::{CreateInteractionResponse, CreateInteractionResponseMessage},
 	http::Http,
@@ -15,10 +15,16 @@ pub trait ReplyShortcuts {
 		S: Into<String> + Send;
 	async fn ephemeral_reply<S>(&self, http: &Arc<Http>, content: S) -> SerenityResult<()>
 	where
-		S: Into<String> + std::marker::Send;
+		S: Into<String> + Send,
+	{
+		self.reply(http, content, true).await
+	}
 	async fn public_reply<S>(&self, http: &Arc<Http>, content: S) -> SerenityResult<()>
 	where
-		S: Into<String> + std::marker::Send;
+		S: Into<String> + Send,
+	{
+		self.reply(http, content, false).await
+	}
 }
 
 #[async_trait]
@@ -37,16 +43,22 @@ impl ReplyShortcuts for CommandInteraction {
 		)
 		.await
 	}
-	async fn ephemeral_reply<S>(&self, http: &Arc<Http>, content: S) -> SerenityResult<()>
-	where
-		S: Into<String> + Send,
-	{
-		self.reply(http, content, true).await
-	}
-	async fn public_reply<S>(&self, http: &Arc<Http>, content: S) -> SerenityResult<()>
+}
+
+#[async_trait]
+impl ReplyShortcuts for ComponentInteraction {
+	async fn reply<S>(&self, http: &Arc<Http>, content: S, ephemeral: bool) -> SerenityResult<()>
 	where
 		S: Into<String> + Send,
 	{
-		self.reply(http, content, false).await
+		self.create_response(
+			http,
+			CreateInteractionResponse::Message(
+				CreateInteractionResponseMessage::new()
+					.content(content)
+					.ephemeral(ephemeral),
+			),
+		)
+		.await
 	}
 }
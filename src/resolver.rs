@@ -0,0 +1,150 @@
+use std::{
+	collections::HashMap,
+	sync::Mutex,
+	time::{Duration, Instant},
+};
+
+use serenity::async_trait;
+
+/// How long to wait for a link to resolve before giving up on it.
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to remember where a link led.
+const CACHE_DURATION: Duration = Duration::from_secs(60 * 60);
+/// How many links to remember at most, so the cache can't grow forever.
+const CACHE_CAPACITY: usize = 1000;
+/// The most redirects to follow for one link.
+const MAX_REDIRECTS: usize = 10;
+/// Path segments of pages that ask to log in, rather than being where the link leads.
+const LOGIN_SEGMENTS: [&str; 4] = ["login", "signin", "sign-in", "sign_in"];
+
+/// Finds out where a link leads, for short links and share links.
+#[async_trait]
+pub trait Resolver: Send + Sync {
+	/// Follows the link's redirects, returning where they end up, or `None` if that could not be found out.
+	async fn resolve(&self, link: &str) -> Option<String>;
+}
+
+/// Resolves links by actually requesting them.
+pub struct HttpResolver {
+	client: reqwest::Client,
+}
+
+impl HttpResolver {
+	pub fn new() -> Self {
+		let client = reqwest::Client::builder()
+			.redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+			.timeout(RESOLVE_TIMEOUT)
+			.user_agent(concat!("linkfixbot/", env!("CARGO_PKG_VERSION")))
+			.build()
+			.expect("Could not build the HTTP client");
+		Self { client }
+	}
+}
+
+#[async_trait]
+impl Resolver for HttpResolver {
+	async fn resolve(&self, link: &str) -> Option<String> {
+		// The body is never read, so this only waits for the headers.
+		let response = self.client.get(link).send().await.ok()?;
+		// Error pages and login walls are not where the link leads.
+		if !response.status().is_success() || is_login_wall(response.url()) {
+			return None;
+		}
+		Some(response.url().to_string())
+	}
+}
+
+/// Whether the page is one asking to log in, going by its path.
+fn is_login_wall(url: &reqwest::Url) -> bool {
+	url.path_segments().is_some_and(|mut segments| {
+		segments.any(|segment| {
+			LOGIN_SEGMENTS
+				.iter()
+				.any(|login| segment.eq_ignore_ascii_case(login))
+		})
+	})
+}
+
+/// Remembers where links led for a while. Failures are not remembered, since they may well be temporary.
+pub struct CachedResolver<R> {
+	inner: R,
+	cache: Mutex<HashMap<String, (Instant, String)>>,
+}
+
+impl<R> CachedResolver<R> {
+	pub fn new(inner: R) -> Self {
+		Self {
+			inner,
+			cache: Mutex::new(HashMap::new()),
+		}
+	}
+}
+
+#[async_trait]
+impl<R: Resolver> Resolver for CachedResolver<R> {
+	async fn resolve(&self, link: &str) -> Option<String> {
+		if let Some((cached_at, resolved)) = self.cache.lock().unwrap().get(link)
+			&& cached_at.elapsed() < CACHE_DURATION
+		{
+			return Some(resolved.clone());
+		}
+		let resolved = self.inner.resolve(link).await?;
+
+		let mut cache = self.cache.lock().unwrap();
+		if cache.len() >= CACHE_CAPACITY {
+			cache.retain(|_, (cached_at, _)| cached_at.elapsed() < CACHE_DURATION);
+			if cache.len() >= CACHE_CAPACITY {
+				cache.clear();
+			}
+		}
+		cache.insert(link.to_string(), (Instant::now(), resolved.clone()));
+		Some(resolved)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{
+		Arc,
+		atomic::{AtomicUsize, Ordering},
+	};
+
+	use super::*;
+	use crate::stub_server;
+
+	#[tokio::test]
+	async fn follow_redirects() {
+		let requests = Arc::new(AtomicUsize::new(0));
+		let counter = requests.clone();
+		let address = stub_server::start(move |_, path| {
+			counter.fetch_add(1, Ordering::Relaxed);
+			match path {
+				"/short" => stub_server::redirect("/middle"),
+				"/middle" => stub_server::redirect("/final?id=3"),
+				"/loop" => stub_server::redirect("/loop"),
+				"/deleted" => stub_server::redirect("/missing"),
+				"/missing" => stub_server::status(404),
+				"/private" => stub_server::redirect("/accounts/login/?next=/private"),
+				_ => stub_server::status(200),
+			}
+		})
+		.await;
+		let resolver = CachedResolver::new(HttpResolver::new());
+
+		let final_link = format!("http://{address}/final?id=3");
+		for _ in 0..2 {
+			assert_eq!(
+				resolver.resolve(&format!("http://{address}/short")).await,
+				Some(final_link.clone())
+			);
+		}
+		assert_eq!(requests.load(Ordering::Relaxed), 3);
+		for path in ["/loop", "/deleted", "/private"] {
+			assert_eq!(
+				resolver.resolve(&format!("http://{address}{path}")).await,
+				None,
+				"{path}"
+			);
+		}
+	}
+}
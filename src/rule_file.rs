@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+	config_error::ConfigError,
+	fix_link::{ConfigDefinition, MirrorDefinition, RuleDefinition, parse_legacy_rules},
+};
+
+/// The structured rule file, a TOML document with one `[[rule]]` table per rule.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+	#[serde(default, rename = "rule")]
+	rules: Vec<RuleEntry>,
+	#[serde(default)]
+	tracking: TrackingEntry,
+	#[serde(default)]
+	markdown: MarkdownEntry,
+	#[serde(default, rename = "mirror")]
+	mirrors: Vec<MirrorEntry>,
+	#[serde(default)]
+	embeds: EmbedsEntry,
+}
+
+/// Settings for removing tracking parameters from links no rule handles.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrackingEntry {
+	#[serde(default)]
+	parameters: Vec<String>,
+	/// The path of a ClearURLs `data.min.json`.
+	clearurls: Option<String>,
+}
+
+/// Settings for which parts of messages to look for links in.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MarkdownEntry {
+	/// Whether to fix links in block quotes. Links in code are never fixed.
+	#[serde(default)]
+	fix_in_quotes: bool,
+}
+
+/// Settings for replacing the embeds of messages with those of the bot's replies.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmbedsEntry {
+	/// In seconds.
+	pairing_timeout: Option<u64>,
+}
+
+/// A mirror whose health to check, by requesting the canary link every so often.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MirrorEntry {
+	host: String,
+	canary: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+	name: String,
+	pattern: Spanned<String>,
+	/// Can only be left out if `resolve` is true.
+	#[serde(default)]
+	replacement: Replacements,
+	#[serde(default = "default_embed")]
+	embed: String,
+	#[serde(default)]
+	resolve: bool,
+	#[serde(default = "default_enabled")]
+	enabled: bool,
+	#[serde(default)]
+	examples: Vec<RuleExample>,
+}
+
+/// Either one replacement, or several in order of preference.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Replacements {
+	One(String),
+	Several(Vec<String>),
+}
+
+impl Default for Replacements {
+	fn default() -> Self {
+		Self::Several(Vec::new())
+	}
+}
+
+impl From<Replacements> for Vec<String> {
+	fn from(replacements: Replacements) -> Self {
+		match replacements {
+			Replacements::One(replacement) => vec![replacement],
+			Replacements::Several(replacements) => replacements,
+		}
+	}
+}
+
+/// An example of what a rule should do with some input, checked whenever the rules are loaded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleExample {
+	pub input: String,
+	/// What the bot should reply with if the input were posted as a message, or `""` if it should not reply at all.
+	pub message: Option<String>,
+	/// What `/fix` should reply with for the input, or `""` if it should find nothing to fix.
+	pub slash: Option<String>,
+}
+
+fn default_embed() -> String {
+	String::from("replace")
+}
+
+fn default_enabled() -> bool {
+	true
+}
+
+/// Whether the config looks like a structured rule file rather than the older line-based format.
+///
+/// The line-based format starts straight away with a pattern, while a rule file starts with a comment or a table header.
+pub fn is_rule_file(config: &str) -> bool {
+	config
+		.lines()
+		.map(str::trim)
+		.find(|line| !line.is_empty())
+		.is_none_or(|line| line.starts_with('#') || line.starts_with('['))
+}
+
+pub fn parse_rule_file(config: &str) -> Result<ConfigDefinition, ConfigError> {
+	let file: RuleFile = toml::from_str(config).map_err(|error| ConfigError::Malformed {
+		line: error.span().map(|span| line_of(config, span.start)),
+		message: error.message().to_string(),
+	})?;
+	let rules = file
+		.rules
+		.into_iter()
+		.enumerate()
+		.filter(|(_, rule)| rule.enabled)
+		.map(|(index, rule)| RuleDefinition {
+			index,
+			line: line_of(config, rule.pattern.span().start),
+			name: rule.name,
+			pattern: rule.pattern.into_inner(),
+			replacements: rule.replacement.into(),
+			embed_handling: rule.embed,
+			resolve: rule.resolve,
+			examples: rule.examples,
+		})
+		.collect();
+	Ok(ConfigDefinition {
+		rules,
+		tracking_parameters: file.tracking.parameters,
+		clearurls: file.tracking.clearurls,
+		fix_in_quotes: file.markdown.fix_in_quotes,
+		pairing_timeout: file.embeds.pairing_timeout.map(Duration::from_secs),
+		mirrors: file
+			.mirrors
+			.into_iter()
+			.map(|mirror| MirrorDefinition {
+				host: mirror.host,
+				canary: mirror.canary,
+			})
+			.collect(),
+	})
+}
+
+/// Converts the older line-based format into a rule file. Rules get placeholder names, since the old format had none.
+pub fn convert_legacy(config: &str) -> Result<String, ConfigError> {
+	let mut output = String::from(
+		"# Converted from the line-based format. Each [[rule]] needs a name, a pattern and a replacement.\n\
+		# `embed` is \"replace\" (the default) or \"do nothing\", and `enabled = false` turns a rule off.\n",
+	);
+	for rule in parse_legacy_rules(config)? {
+		output.push_str(&format!(
+			"\n[[rule]]\nname = {}\npattern = {}\nreplacement = {}\nembed = {}\n",
+			toml_string(&rule.name),
+			toml_pattern(&rule.pattern),
+			toml_string(&rule.replacements[0]),
+			toml_string(&rule.embed_handling),
+		));
+	}
+	Ok(output)
+}
+
+fn toml_string(string: &str) -> String {
+	toml::Value::String(string.to_string()).to_string()
+}
+
+/// Formats a pattern as a TOML literal string where possible, so its backslashes stay readable.
+fn toml_pattern(pattern: &str) -> String {
+	if pattern.contains(['\'', '\n', '\r']) {
+		toml_string(pattern)
+	} else {
+		format!("'{pattern}'")
+	}
+}
+
+/// The 1-based line number of a byte offset.
+pub fn line_of(config: &str, offset: usize) -> usize {
+	config[..offset].matches('\n').count() + 1
+}
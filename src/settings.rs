@@ -0,0 +1,453 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use rusqlite::{Connection, OptionalExtension, params};
+use serenity::all::{ChannelId, GuildId, UserId};
+
+use crate::database::Database;
+
+/// What a server changed about how the bot behaves there. The defaults are how it behaves anywhere else, like in DMs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuildSettings {
+	/// Whether to reply to messages with links without being asked to.
+	pub automatic: bool,
+	/// Whether to react to messages whose links were fixed on request.
+	pub reactions: bool,
+	/// Whether to suppress the embeds of the original message once the fixed links have theirs.
+	pub suppress_embeds: bool,
+	/// The names of the rules not to apply.
+	pub disabled_rules: HashSet<String>,
+	/// The host to prefer for each rule with several replacements, by rule name.
+	pub mirrors: HashMap<String, String>,
+	/// Channels, categories and threads where automatic fixing goes against `automatic`.
+	pub channels: HashMap<ChannelId, ChannelRule>,
+}
+
+impl Default for GuildSettings {
+	fn default() -> Self {
+		Self {
+			automatic: true,
+			reactions: true,
+			suppress_embeds: true,
+			disabled_rules: HashSet::new(),
+			mirrors: HashMap::new(),
+			channels: HashMap::new(),
+		}
+	}
+}
+
+impl GuildSettings {
+	pub fn rule_enabled(&self, rule: &str) -> bool {
+		!self.disabled_rules.contains(rule)
+	}
+	pub fn preferred_mirror(&self, rule: &str) -> Option<&str> {
+		self.mirrors.get(rule).map(String::as_str)
+	}
+	/// Whether to reply to messages in the channel without being asked to, given the channel followed by what it is in (see `channel_lineage`). The rule for the most specific of those wins, and the server's setting applies if none of them have one.
+	pub fn automatic_in(&self, lineage: &[ChannelId]) -> bool {
+		match lineage
+			.iter()
+			.find_map(|channel| self.channels.get(channel))
+		{
+			Some(rule) => *rule == ChannelRule::Allow,
+			None => self.automatic,
+		}
+	}
+	pub fn get(&self, toggle: Toggle) -> bool {
+		match toggle {
+			Toggle::Automatic => self.automatic,
+			Toggle::Reactions => self.reactions,
+			Toggle::SuppressEmbeds => self.suppress_embeds,
+		}
+	}
+}
+
+/// A setting a server can turn on or off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toggle {
+	Automatic,
+	Reactions,
+	SuppressEmbeds,
+}
+
+impl Toggle {
+	pub const ALL: [Self; 3] = [Self::Automatic, Self::Reactions, Self::SuppressEmbeds];
+	/// Also the name of its column in the database.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Automatic => "automatic",
+			Self::Reactions => "reactions",
+			Self::SuppressEmbeds => "suppress_embeds",
+		}
+	}
+	pub fn from_name(name: &str) -> Option<Self> {
+		Self::ALL.into_iter().find(|toggle| toggle.name() == name)
+	}
+	pub fn description(self) -> &'static str {
+		match self {
+			Self::Automatic => "Automatic fixing",
+			Self::Reactions => "Reactions",
+			Self::SuppressEmbeds => "Embed suppression",
+		}
+	}
+}
+
+/// Whether automatic fixing happens in a channel, regardless of the server's setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelRule {
+	Allow,
+	Deny,
+}
+
+impl ChannelRule {
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::Allow => "allow",
+			Self::Deny => "deny",
+		}
+	}
+	pub fn from_name(name: &str) -> Option<Self> {
+		[Self::Allow, Self::Deny]
+			.into_iter()
+			.find(|rule| rule.name() == name)
+	}
+}
+
+/// The settings of every server, in the database.
+pub struct SettingsStore {
+	database: Database,
+}
+
+impl SettingsStore {
+	/// Creates the tables for the settings in the database if needed.
+	pub async fn new(database: Database) -> rusqlite::Result<Self> {
+		database
+			.run(|connection| {
+				connection.execute_batch(
+					"CREATE TABLE IF NOT EXISTS guilds (
+						guild INTEGER PRIMARY KEY,
+						automatic INTEGER NOT NULL DEFAULT 1,
+						reactions INTEGER NOT NULL DEFAULT 1,
+						suppress_embeds INTEGER NOT NULL DEFAULT 1
+					);
+					CREATE TABLE IF NOT EXISTS disabled_rules (
+						guild INTEGER NOT NULL,
+						rule TEXT NOT NULL,
+						PRIMARY KEY (guild, rule)
+					);
+					CREATE TABLE IF NOT EXISTS preferred_mirrors (
+						guild INTEGER NOT NULL,
+						rule TEXT NOT NULL,
+						host TEXT NOT NULL,
+						PRIMARY KEY (guild, rule)
+					);
+					CREATE TABLE IF NOT EXISTS channel_rules (
+						guild INTEGER NOT NULL,
+						channel INTEGER NOT NULL,
+						rule TEXT NOT NULL,
+						PRIMARY KEY (guild, channel)
+					);
+					CREATE TABLE IF NOT EXISTS opt_outs (
+						user INTEGER NOT NULL,
+						guild INTEGER NOT NULL,
+						PRIMARY KEY (user, guild)
+					);",
+				)
+			})
+			.await?;
+		Ok(Self { database })
+	}
+	/// The settings of the server, or the defaults if they could not be read.
+	pub async fn get(&self, guild: GuildId) -> GuildSettings {
+		self.database
+			.run(move |connection| read_settings(connection, guild))
+			.await
+			.unwrap_or_else(|error| {
+				eprintln!(
+					"Could not read the settings of guild {}, so I used the defaults: {error}",
+					guild.get()
+				);
+				GuildSettings::default()
+			})
+	}
+	/// The settings of the server if there is one, or the defaults otherwise.
+	pub async fn get_optional(&self, guild: Option<GuildId>) -> GuildSettings {
+		match guild {
+			Some(guild) => self.get(guild).await,
+			None => GuildSettings::default(),
+		}
+	}
+	pub async fn set_toggle(
+		&self,
+		guild: GuildId,
+		toggle: Toggle,
+		on: bool,
+	) -> rusqlite::Result<()> {
+		self.database
+			.run(move |connection| {
+				connection.execute(
+					&format!(
+						"INSERT INTO guilds (guild, {0}) VALUES (?1, ?2)
+						ON CONFLICT (guild) DO UPDATE SET {0} = excluded.{0}",
+						toggle.name()
+					),
+					params![guild.get(), on],
+				)
+			})
+			.await?;
+		Ok(())
+	}
+	pub async fn set_rule_enabled(
+		&self,
+		guild: GuildId,
+		rule: &str,
+		enabled: bool,
+	) -> rusqlite::Result<()> {
+		let query = if enabled {
+			"DELETE FROM disabled_rules WHERE guild = ?1 AND rule = ?2"
+		} else {
+			"INSERT OR IGNORE INTO disabled_rules (guild, rule) VALUES (?1, ?2)"
+		};
+		let rule = rule.to_string();
+		self.database
+			.run(move |connection| connection.execute(query, params![guild.get(), rule]))
+			.await?;
+		Ok(())
+	}
+	/// Makes the rule prefer the host, or go back to its own order with `None`.
+	pub async fn set_mirror(
+		&self,
+		guild: GuildId,
+		rule: &str,
+		host: Option<&str>,
+	) -> rusqlite::Result<()> {
+		let (rule, host) = (rule.to_string(), host.map(str::to_string));
+		self.database
+			.run(move |connection| match host {
+				Some(host) => connection.execute(
+					"INSERT OR REPLACE INTO preferred_mirrors (guild, rule, host) VALUES (?1, ?2, ?3)",
+					params![guild.get(), rule, host],
+				),
+				None => connection.execute(
+					"DELETE FROM preferred_mirrors WHERE guild = ?1 AND rule = ?2",
+					params![guild.get(), rule],
+				),
+			})
+			.await?;
+		Ok(())
+	}
+	/// Stops automatic replies to the user's messages in the server, or in every server with `None`. Opting back in everywhere also undoes opting out of single servers.
+	pub async fn set_opted_out(
+		&self,
+		user: UserId,
+		guild: Option<GuildId>,
+		opted_out: bool,
+	) -> rusqlite::Result<()> {
+		let guild = opt_out_scope(guild);
+		self.database
+			.run(move |connection| {
+				if opted_out {
+					connection.execute(
+						"INSERT OR IGNORE INTO opt_outs (user, guild) VALUES (?1, ?2)",
+						params![user.get(), guild],
+					)
+				} else if guild == EVERYWHERE {
+					connection.execute("DELETE FROM opt_outs WHERE user = ?1", params![user.get()])
+				} else {
+					connection.execute(
+						"DELETE FROM opt_outs WHERE user = ?1 AND guild = ?2",
+						params![user.get(), guild],
+					)
+				}
+			})
+			.await?;
+		Ok(())
+	}
+	/// Whether the user opted out of automatic replies in the server, or everywhere. In DMs, only the latter counts.
+	pub async fn is_opted_out(&self, user: UserId, guild: Option<GuildId>) -> bool {
+		let guild = opt_out_scope(guild);
+		self.database
+			.run(move |connection| {
+				connection.query_row(
+					"SELECT EXISTS (SELECT 1 FROM opt_outs WHERE user = ?1 AND guild IN (?2, ?3))",
+					params![user.get(), EVERYWHERE, guild],
+					|row| row.get(0),
+				)
+			})
+			.await
+			.unwrap_or_else(|error| {
+				eprintln!(
+					"Could not check whether user {} opted out, so I assumed not: {error}",
+					user.get()
+				);
+				false
+			})
+	}
+	/// Sets whether automatic fixing happens in the channel, or makes it follow the server's setting again with `None`.
+	pub async fn set_channel_rule(
+		&self,
+		guild: GuildId,
+		channel: ChannelId,
+		rule: Option<ChannelRule>,
+	) -> rusqlite::Result<()> {
+		self.database
+			.run(move |connection| match rule {
+				Some(rule) => connection.execute(
+					"INSERT OR REPLACE INTO channel_rules (guild, channel, rule) VALUES (?1, ?2, ?3)",
+					params![guild.get(), channel.get(), rule.name()],
+				),
+				None => connection.execute(
+					"DELETE FROM channel_rules WHERE guild = ?1 AND channel = ?2",
+					params![guild.get(), channel.get()],
+				),
+			})
+			.await?;
+		Ok(())
+	}
+}
+
+fn read_settings(connection: &Connection, guild: GuildId) -> rusqlite::Result<GuildSettings> {
+	let guild = guild.get();
+	let mut settings = connection
+		.query_row(
+			"SELECT automatic, reactions, suppress_embeds FROM guilds WHERE guild = ?1",
+			params![guild],
+			|row| {
+				Ok(GuildSettings {
+					automatic: row.get(0)?,
+					reactions: row.get(1)?,
+					suppress_embeds: row.get(2)?,
+					..GuildSettings::default()
+				})
+			},
+		)
+		.optional()?
+		.unwrap_or_default();
+	settings.disabled_rules = connection
+		.prepare("SELECT rule FROM disabled_rules WHERE guild = ?1")?
+		.query_map(params![guild], |row| row.get(0))?
+		.collect::<Result<_, _>>()?;
+	settings.mirrors = connection
+		.prepare("SELECT rule, host FROM preferred_mirrors WHERE guild = ?1")?
+		.query_map(params![guild], |row| Ok((row.get(0)?, row.get(1)?)))?
+		.collect::<Result<_, _>>()?;
+	settings.channels = connection
+		.prepare("SELECT channel, rule FROM channel_rules WHERE guild = ?1")?
+		.query_map(params![guild], |row| {
+			Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?))
+		})?
+		.filter_map_ok(|(channel, rule)| {
+			Some((ChannelId::new(channel), ChannelRule::from_name(&rule)?))
+		})
+		.collect::<Result<_, _>>()?;
+	Ok(settings)
+}
+
+/// Stands for every server in the opt-outs, since no server has it as its ID.
+const EVERYWHERE: u64 = 0;
+
+fn opt_out_scope(guild: Option<GuildId>) -> u64 {
+	guild.map_or(EVERYWHERE, GuildId::get)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn store_settings() {
+		let store = SettingsStore::new(Database::open(":memory:").unwrap())
+			.await
+			.unwrap();
+		let guild = GuildId::new(1);
+		assert_eq!(store.get(guild).await, GuildSettings::default());
+
+		store
+			.set_toggle(guild, Toggle::Automatic, false)
+			.await
+			.unwrap();
+		store
+			.set_toggle(guild, Toggle::SuppressEmbeds, false)
+			.await
+			.unwrap();
+		store
+			.set_toggle(guild, Toggle::SuppressEmbeds, true)
+			.await
+			.unwrap();
+		store.set_rule_enabled(guild, "x", false).await.unwrap();
+		store
+			.set_rule_enabled(guild, "instagram", false)
+			.await
+			.unwrap();
+		store
+			.set_rule_enabled(guild, "instagram", true)
+			.await
+			.unwrap();
+		store
+			.set_mirror(guild, "tiktok", Some("www.tnktok.com"))
+			.await
+			.unwrap();
+		store
+			.set_channel_rule(guild, ChannelId::new(10), Some(ChannelRule::Allow))
+			.await
+			.unwrap();
+		store
+			.set_rule_enabled(GuildId::new(2), "tiktok", false)
+			.await
+			.unwrap();
+
+		let settings = store.get(guild).await;
+		assert!(!settings.automatic && settings.reactions && settings.suppress_embeds);
+		assert!(!settings.rule_enabled("x") && settings.rule_enabled("instagram"));
+		assert!(settings.rule_enabled("tiktok"));
+		assert_eq!(settings.preferred_mirror("tiktok"), Some("www.tnktok.com"));
+		assert!(settings.automatic_in(&[ChannelId::new(10)]));
+		assert!(!settings.automatic_in(&[ChannelId::new(11)]));
+
+		// A denied category with an allowed channel in it, which has a thread.
+		let [category, allowed, other, thread] = [20, 21, 22, 23].map(ChannelId::new);
+		store
+			.set_toggle(guild, Toggle::Automatic, true)
+			.await
+			.unwrap();
+		store
+			.set_channel_rule(guild, category, Some(ChannelRule::Deny))
+			.await
+			.unwrap();
+		store
+			.set_channel_rule(guild, allowed, Some(ChannelRule::Allow))
+			.await
+			.unwrap();
+		let settings = store.get(guild).await;
+		assert!(!settings.automatic_in(&[other, category]));
+		assert!(settings.automatic_in(&[allowed, category]));
+		assert!(settings.automatic_in(&[thread, allowed, category]));
+		assert!(settings.automatic_in(&[ChannelId::new(30)]));
+		assert_eq!(store.get(GuildId::new(3)).await, GuildSettings::default());
+	}
+	#[tokio::test]
+	async fn opt_outs() {
+		let store = SettingsStore::new(Database::open(":memory:").unwrap())
+			.await
+			.unwrap();
+		let (user, other_user) = (UserId::new(1), UserId::new(2));
+		let (guild, other_guild) = (Some(GuildId::new(10)), Some(GuildId::new(11)));
+
+		store.set_opted_out(user, guild, true).await.unwrap();
+		assert!(store.is_opted_out(user, guild).await);
+		assert!(!store.is_opted_out(user, other_guild).await);
+		assert!(!store.is_opted_out(user, None).await);
+		assert!(!store.is_opted_out(other_user, guild).await);
+
+		store.set_opted_out(user, None, true).await.unwrap();
+		assert!(
+			store.is_opted_out(user, other_guild).await && store.is_opted_out(user, None).await
+		);
+		store.set_opted_out(user, guild, false).await.unwrap();
+		assert!(store.is_opted_out(user, guild).await);
+
+		store.set_opted_out(user, guild, true).await.unwrap();
+		store.set_opted_out(user, None, false).await.unwrap();
+		assert!(!store.is_opted_out(user, guild).await && !store.is_opted_out(user, None).await);
+	}
+}
@@ -1,9 +1,18 @@
 use itertools::Itertools;
 use serenity::all::*;
 
-use crate::{fix_link::LinkFixer, reply_shortcuts::ReplyShortcuts, strings::ERROR_NONE_FOUND};
+use crate::{
+	fix_link::LinkFixer, reply_shortcuts::ReplyShortcuts, resolver::Resolver,
+	settings::GuildSettings, strings::ERROR_NONE_FOUND,
+};
 
-pub async fn fix_links(context: &Context, interaction: CommandInteraction, link_fixer: &LinkFixer) {
+pub async fn fix_links(
+	context: &Context,
+	interaction: CommandInteraction,
+	link_fixer: &LinkFixer,
+	resolver: &dyn Resolver,
+	settings: &GuildSettings,
+) {
 	let Some(content) = interaction
 		.data
 		.options
@@ -13,7 +22,9 @@ pub async fn fix_links(context: &Context, interaction: CommandInteraction, link_
 		return;
 	};
 	let output = link_fixer
-		.find_and_fix_slash(content)
+		.find_and_fix_resolving(content, false, resolver, settings)
+		.await
+		.into_iter()
 		.map(|fix| fix.fixed)
 		.join("\n");
 
@@ -1 +1 @@
-pub const ERROR_NONE_FOUND: &str = "Found no links to fix. I only fix embed links for x.com, instagram.com and reddit.com, unshort Youtube shorts links, and clean up Amazon links.";
+pub const ERROR_NONE_FOUND: &str = "Found no links to fix. I only fix embed links for x.com, instagram.com and reddit.com, unshort Youtube shorts links, clean up Amazon links, and remove tracking parameters from other links.";
@@ -0,0 +1,49 @@
+//! A tiny HTTP server for tests, answering every request through a function.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::TcpListener,
+};
+
+/// Starts serving on a free local port, answering each request with what `respond` returns for its method and path.
+pub async fn start(respond: impl Fn(&str, &str) -> String + Send + Sync + 'static) -> SocketAddr {
+	let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+	let address = listener.local_addr().unwrap();
+	let respond = Arc::new(respond);
+	tokio::spawn(async move {
+		while let Ok((mut stream, _)) = listener.accept().await {
+			let respond = respond.clone();
+			tokio::spawn(async move {
+				let mut request = Vec::new();
+				let mut buffer = [0; 1024];
+				while !request.ends_with(b"\r\n\r\n") {
+					match stream.read(&mut buffer).await {
+						Ok(0) | Err(_) => return,
+						Ok(read) => request.extend_from_slice(&buffer[..read]),
+					}
+				}
+				let request = String::from_utf8_lossy(&request);
+				let mut request_line = request.split_whitespace();
+				let method = request_line.next().unwrap_or_default();
+				let path = request_line.next().unwrap_or_default();
+				let response = respond(method, path);
+				let _ = stream.write_all(response.as_bytes()).await;
+			});
+		}
+	});
+	address
+}
+
+/// An empty response with the status code.
+pub fn status(code: u16) -> String {
+	format!("HTTP/1.1 {code} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+}
+
+/// A redirect to the location.
+pub fn redirect(location: &str) -> String {
+	format!(
+		"HTTP/1.1 301 Moved Permanently\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+	)
+}
@@ -0,0 +1,263 @@
+use std::ops::Range;
+
+use crate::markdown::spoiler_ranges;
+
+/// A link found in a message, the way Discord's own autolinker would find it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkToken<'t> {
+	/// The link itself, without any `<>` around it or punctuation after it.
+	pub link: &'t str,
+	/// Where the link is in the text, in bytes, including any `<>` around it.
+	pub span: Range<usize>,
+	/// Whether the link was wrapped in `<>` to suppress its embed.
+	pub suppressed: bool,
+	/// The text shown in place of the link, if it was a masked link like `[text](https://example.com)`. The span then covers the whole masked link.
+	pub label: Option<&'t str>,
+	/// Whether the link is inside spoiler tags.
+	pub spoiler: bool,
+}
+
+impl LinkToken<'_> {
+	/// Puts a replacement for the link in the same Markdown the link was in, keeping any label and spoiler tags.
+	pub fn rewrap(&self, link: String) -> String {
+		let link = match self.label {
+			Some(label) => format!("[{label}]({link})"),
+			None => link,
+		};
+		if self.spoiler {
+			format!("||{link}||")
+		} else {
+			link
+		}
+	}
+}
+
+/// Finds the links in the text, including masked links. Like Discord, it finds `http://` and `https://` links anywhere, even in the middle of a word, up to whitespace, a `<` or spoiler tags. Trailing punctuation is left off, except for a `)` that closes a `(` in the link, so `(https://example.com/a_(b))` gives `https://example.com/a_(b)`.
+pub fn find_links(text: &str) -> Vec<LinkToken<'_>> {
+	let spoilers = spoiler_ranges(text);
+	let mut links = Vec::new();
+	let mut start = 0;
+	while let Some(rest) = text.get(start..).filter(|rest| !rest.is_empty()) {
+		let found = match rest.chars().next() {
+			Some('<') => suppressed_link_len(rest).map(|len| (None, 1..len - 1, len, true)),
+			Some('[') => masked_link(rest),
+			_ => link_len(rest).map(|len| (None, 0..len, len, false)),
+		};
+		match found {
+			Some((label, link, len, suppressed)) => {
+				let span = start..start + len;
+				links.push(LinkToken {
+					link: &rest[link],
+					spoiler: spoilers
+						.iter()
+						.any(|spoiler| spoiler.start <= span.start && span.end <= spoiler.end),
+					span,
+					suppressed,
+					label,
+				});
+				start += len;
+			}
+			None => start += rest.chars().next().unwrap().len_utf8(),
+		}
+	}
+	links
+}
+
+/// What links have to start with, in any letter case.
+pub const SCHEMES: [&str; 2] = ["https://", "http://"];
+
+/// Whether the character ends a link, so links never contain it.
+pub fn ends_link(char: char) -> bool {
+	char.is_whitespace() || char == '<'
+}
+
+/// The length of the scheme the text starts with, if it is `http://` or `https://`.
+fn scheme_len(text: &str) -> Option<usize> {
+	SCHEMES.into_iter().find_map(|scheme| {
+		text.as_bytes()
+			.get(..scheme.len())
+			.is_some_and(|start| start.eq_ignore_ascii_case(scheme.as_bytes()))
+			.then_some(scheme.len())
+	})
+}
+
+/// The length of the link the text starts with, if it starts with one.
+fn link_len(text: &str) -> Option<usize> {
+	let scheme_len = scheme_len(text)?;
+	let mut end = text.find(ends_link).unwrap_or(text.len());
+	if let Some(spoiler_tags) = text[..end].find("||") {
+		end = spoiler_tags;
+	}
+	loop {
+		let link = &text[..end];
+		match link.chars().last() {
+			Some('.' | ',' | ':' | ';' | '"' | '\'' | ']') => end -= 1,
+			Some(')') if link.matches(')').count() > link.matches('(').count() => end -= 1,
+			_ => break,
+		}
+	}
+	(end > scheme_len).then_some(end)
+}
+
+/// The length of the `<link>` the text starts with, if it starts with one. The link can't contain whitespace.
+fn suppressed_link_len(text: &str) -> Option<usize> {
+	let inner = text.strip_prefix('<')?;
+	let inner_len = inner.find(|char: char| ends_link(char) || char == '>')?;
+	if !inner[inner_len..].starts_with('>') || inner_len <= scheme_len(inner)? {
+		return None;
+	}
+	Some(inner_len + 2)
+}
+
+/// The label, where the link is, the total length and whether the link is suppressed, of the masked link the text starts with, if it starts with one. The link can be wrapped in `<>` and can contain balanced parentheses.
+fn masked_link(text: &str) -> Option<(Option<&str>, Range<usize>, usize, bool)> {
+	let label_len = text[1..].find([']', '\n'])?;
+	let label = &text[1..1 + label_len];
+	let link_start = 1 + label_len + 2;
+	if label.is_empty() || !text[1 + label_len..].starts_with("](") {
+		return None;
+	}
+	let rest = &text[link_start..];
+	if rest.starts_with('<') {
+		let len = suppressed_link_len(rest)?;
+		return rest[len..].starts_with(')').then_some((
+			Some(label),
+			link_start + 1..link_start + len - 1,
+			link_start + len + 1,
+			true,
+		));
+	}
+	let scheme_len = scheme_len(rest)?;
+	let mut depth = 0_usize;
+	for (index, char) in rest.char_indices() {
+		match char {
+			'(' => depth += 1,
+			')' if depth == 0 => {
+				return (index > scheme_len).then_some((
+					Some(label),
+					link_start..link_start + index,
+					link_start + index + 1,
+					false,
+				));
+			}
+			')' => depth -= 1,
+			char if ends_link(char) => return None,
+			_ => (),
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn links(text: &str) -> Vec<&str> {
+		find_links(text)
+			.into_iter()
+			.map(|token| token.link)
+			.collect()
+	}
+
+	#[test]
+	fn surrounding_punctuation() {
+		assert_eq!(
+			links(
+				"(https://x.com/a/status/1) link:https://example.com/a, https://example.com/b. \"https://example.com/c\""
+			),
+			[
+				"https://x.com/a/status/1",
+				"https://example.com/a",
+				"https://example.com/b",
+				"https://example.com/c",
+			]
+		);
+		assert_eq!(
+			links("see https://en.wikipedia.org/wiki/Rust_(programming_language)."),
+			["https://en.wikipedia.org/wiki/Rust_(programming_language)"]
+		);
+		assert_eq!(links("https:// http://."), Vec::<&str>::new());
+	}
+	#[test]
+	fn unicode_whitespace() {
+		assert_eq!(
+			links("HTTPS://example.com/ä\u{a0}next\u{3000}https://example.com/b"),
+			["HTTPS://example.com/ä", "https://example.com/b"]
+		);
+	}
+	#[test]
+	fn suppressed() {
+		let text = "a <https://example.com/a> <https://example.com/b c> <nope>";
+		assert_eq!(
+			find_links(text),
+			[
+				LinkToken {
+					link: "https://example.com/a",
+					span: 2..25,
+					suppressed: true,
+					label: None,
+					spoiler: false,
+				},
+				LinkToken {
+					link: "https://example.com/b",
+					span: 27..48,
+					suppressed: false,
+					label: None,
+					spoiler: false,
+				},
+			]
+		);
+	}
+	#[test]
+	fn masked() {
+		let text = "[a post](https://x.com/a/status/1) [b](<https://example.com/b_(c)>), [c] (https://example.com/c) [d](https://example.com/d e)";
+		assert_eq!(
+			find_links(text),
+			[
+				LinkToken {
+					link: "https://x.com/a/status/1",
+					span: 0..34,
+					suppressed: false,
+					label: Some("a post"),
+					spoiler: false,
+				},
+				LinkToken {
+					link: "https://example.com/b_(c)",
+					span: 35..67,
+					suppressed: true,
+					label: Some("b"),
+					spoiler: false,
+				},
+				LinkToken {
+					link: "https://example.com/c",
+					span: 74..95,
+					suppressed: false,
+					label: None,
+					spoiler: false,
+				},
+				LinkToken {
+					link: "https://example.com/d",
+					span: 101..122,
+					suppressed: false,
+					label: None,
+					spoiler: false,
+				},
+			]
+		);
+	}
+	#[test]
+	fn spoilers() {
+		let text = "||spoiler|| https://example.com/a ||https://example.com/b|| ||[c](https://example.com/c)";
+		assert_eq!(
+			find_links(text)
+				.into_iter()
+				.map(|token| (token.link, token.spoiler))
+				.collect::<Vec<_>>(),
+			[
+				("https://example.com/a", false),
+				("https://example.com/b", true),
+				("https://example.com/c", false),
+			]
+		);
+	}
+}
@@ -0,0 +1,102 @@
+use crate::clearurls::Provider;
+
+/// A query parameter to remove, like `fbclid`, or `utm_*` for every parameter starting with `utm_`.
+#[derive(Debug)]
+enum ParameterPattern {
+	Exact(String),
+	Prefix(String),
+}
+
+impl ParameterPattern {
+	fn new(pattern: &str) -> Self {
+		let pattern = pattern.to_ascii_lowercase();
+		match pattern.strip_suffix('*') {
+			Some(prefix) => Self::Prefix(prefix.to_string()),
+			None => Self::Exact(pattern),
+		}
+	}
+	fn matches(&self, parameter: &str) -> bool {
+		let parameter = parameter.to_ascii_lowercase();
+		match self {
+			Self::Exact(name) => parameter == *name,
+			Self::Prefix(prefix) => parameter.starts_with(prefix.as_str()),
+		}
+	}
+}
+
+/// Removes tracking parameters from the query strings of links, and unwraps redirect links.
+#[derive(Debug, Default)]
+pub struct TrackingCleaner {
+	parameters: Vec<ParameterPattern>,
+	/// Rules per site, imported from ClearURLs.
+	providers: Vec<Provider>,
+}
+
+impl TrackingCleaner {
+	pub fn new(parameters: &[String], providers: Vec<Provider>) -> Self {
+		Self {
+			parameters: parameters
+				.iter()
+				.map(|parameter| ParameterPattern::new(parameter))
+				.collect(),
+			providers,
+		}
+	}
+	/// Returns the cleaned link, or `None` if there was nothing to clean. A redirect link is replaced by its (cleaned) target.
+	pub fn clean(&self, link: &str) -> Option<String> {
+		let providers = self
+			.providers
+			.iter()
+			.filter(|provider| provider.applies_to(link))
+			.collect::<Vec<_>>();
+		if let Some(target) = providers
+			.iter()
+			.find_map(|provider| provider.redirect(link))
+		{
+			// The target is part of the link, so this can't go on forever.
+			return Some(self.clean(&target).unwrap_or(target));
+		}
+
+		let cleaned = providers.iter().fold(link.to_string(), |link, provider| {
+			provider.remove_raw_rules(&link)
+		});
+		let cleaned = remove_parameters(&cleaned, |name| {
+			self.parameters.iter().any(|pattern| pattern.matches(name))
+				|| providers
+					.iter()
+					.any(|provider| provider.is_tracking_parameter(name))
+		});
+		(cleaned != link).then_some(cleaned)
+	}
+}
+
+/// Removes the query parameters whose names match.
+fn remove_parameters(link: &str, is_tracking: impl Fn(&str) -> bool) -> String {
+	let (without_fragment, fragment) = match link.split_once('#') {
+		Some((link, fragment)) => (link, Some(fragment)),
+		None => (link, None),
+	};
+	let Some((base, query)) = without_fragment.split_once('?') else {
+		return link.to_string();
+	};
+	let kept = query
+		.split('&')
+		.filter(|parameter| {
+			let name = parameter
+				.split_once('=')
+				.map_or(*parameter, |(name, _)| name);
+			!is_tracking(name)
+		})
+		.collect::<Vec<_>>();
+
+	let mut cleaned = base.to_string();
+	if !kept.is_empty() {
+		cleaned.push('?');
+		cleaned.push_str(&kept.join("&"));
+	}
+	if let Some(fragment) = fragment {
+		cleaned.push('#');
+		cleaned.push_str(fragment);
+	}
+	cleaned
+}
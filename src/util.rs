@@ -1,15 +1,11 @@
-use std::sync::LazyLock;
+use std::{
+	sync::LazyLock,
+	time::{SystemTime, UNIX_EPOCH},
+};
 
 use regex::Regex;
 use serenity::all::{Embed, Message, MessageFlags};
 
-static SPOILERS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\|\|").unwrap());
-
-/// Overly aggressive function detecting any possible presence of spoiler tags.
-pub fn has_spoilers(str: &str) -> bool {
-	SPOILERS.captures_iter(str).nth(1).is_some()
-}
-
 static X: LazyLock<Regex> =
 	LazyLock::new(|| Regex::new(r"(?i)^https://x\.com/([0-9a-z_]+/status/[0-9]+\S*)$").unwrap());
 
@@ -49,3 +45,22 @@ pub fn count_embeds<'l>(embeds: impl IntoIterator<Item = &'l Embed>) -> usize {
 	}
 	count
 }
+
+/// The current time in seconds since the Unix epoch, for saving.
+pub fn unix_time() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |time| time.as_secs())
+}
+
+/// The most characters Discord allows in a message.
+const MESSAGE_LIMIT: usize = 2000;
+
+/// Cuts a message down to what Discord allows, marking that it was cut.
+pub fn truncate_message(mut message: String) -> String {
+	if message.chars().count() > MESSAGE_LIMIT {
+		message = message.chars().take(MESSAGE_LIMIT - 1).collect();
+		message.push('…');
+	}
+	message
+}